@@ -0,0 +1,145 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The loop a deployed agent runs, polling the remote on an interval and
+//! backing off while the remote fails.
+
+use crate::AppImpl;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of time for the agent, so the loop can be driven by a fake one.
+#[async_trait::async_trait(?Send)]
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The system's clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[async_trait::async_trait(?Send)]
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        futures_timer::Delay::new(duration).await
+    }
+}
+
+/// When the agent polls the remote.
+#[derive(Clone, Debug)]
+pub struct Polling {
+    pub interval: Duration,
+    /// Upper bound of the random delay added to every wait, so devices
+    /// started together don't poll together.
+    pub jitter: Duration,
+    /// Wait after the first failed poll, doubled by each one that follows.
+    pub backoff: Duration,
+    /// Longest wait between failed polls.
+    pub max_backoff: Duration,
+}
+
+impl Default for Polling {
+    fn default() -> Self {
+        Polling {
+            interval: Duration::from_secs(60 * 60),
+            jitter: Duration::from_secs(5 * 60),
+            backoff: Duration::from_secs(30),
+            max_backoff: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+pub struct Agent<A, C> {
+    app: A,
+    clock: C,
+    /// Polls that failed in a row.
+    failures: u32,
+    rng: u64,
+}
+
+impl<A: AppImpl, C: Clock> Agent<A, C> {
+    pub fn new(app: A, clock: C) -> Self {
+        let seed = clock.now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        // The generator gets stuck at zero
+        Agent { app, clock, failures: 0, rng: seed | 1 }
+    }
+
+    pub fn app(&mut self) -> &mut A {
+        &mut self.app
+    }
+
+    /// Polls forever, only stopping at an error the app can't recover from.
+    pub async fn run(mut self) -> A::Err {
+        loop {
+            if let Err(e) = self.tick().await {
+                return e;
+            }
+        }
+    }
+
+    /// Waits for the poll scheduled in `Info::next_poll`, if it is still
+    /// ahead, and then polls.
+    pub async fn tick(&mut self) -> Result<(), A::Err> {
+        let mut next_poll = None;
+        self.app.map_info(|info| next_poll = info.next_poll).await?;
+        if let Some(next_poll) = next_poll {
+            let at = UNIX_EPOCH + Duration::from_secs(next_poll);
+            if let Ok(wait) = at.duration_since(self.clock.now()) {
+                self.clock.sleep(wait).await;
+            }
+        }
+        self.poll().await
+    }
+
+    /// Processes the remote right away and schedules the next poll, backing
+    /// off if the remote failed.
+    pub async fn poll(&mut self) -> Result<(), A::Err> {
+        let mut errors_before = 0;
+        self.app.map_info(|info| errors_before = info.count_remote_errors).await?;
+        let processed = self.app.process(&self.clock).await;
+        let mut errors_after = 0;
+        self.app.map_info(|info| errors_after = info.count_remote_errors).await?;
+
+        if processed.is_ok() && errors_after == errors_before {
+            self.failures = 0;
+        } else {
+            self.failures = self.failures.saturating_add(1);
+        }
+        let wait = self.wait() + self.jitter();
+        let next_poll = (self.clock.now() + wait).duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.app.update_info(|info| info.next_poll = Some(next_poll.as_secs())).await?;
+
+        processed
+    }
+
+    fn wait(&self) -> Duration {
+        let polling = &self.app.config().polling;
+        match self.failures {
+            0 => polling.interval,
+            n => {
+                let factor = 1u32.checked_shl(n - 1).unwrap_or(u32::MAX);
+                polling
+                    .backoff
+                    .checked_mul(factor)
+                    .map_or(polling.max_backoff, |backoff| backoff.min(polling.max_backoff))
+            }
+        }
+    }
+
+    fn jitter(&mut self) -> Duration {
+        let max = self.app.config().polling.jitter.as_millis() as u64;
+        if max == 0 {
+            return Duration::default();
+        }
+        // xorshift64, which is plenty to spread devices apart
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        Duration::from_millis(self.rng % max)
+    }
+}
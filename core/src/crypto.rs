@@ -0,0 +1,24 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Cryptographic primitives the agent relies on. They are backed by openssl
+//! with the `crypto-openssl` feature, or by pure Rust crates with the
+//! `crypto-rust` one, so the cost of each can be compared. Only one of them
+//! can be enabled, so a measurement never links both.
+
+#[cfg(not(any(feature = "crypto-openssl", feature = "crypto-rust")))]
+compile_error!("either the `crypto-openssl` or the `crypto-rust` feature must be enabled");
+
+#[cfg(all(feature = "crypto-openssl", feature = "crypto-rust"))]
+compile_error!("the `crypto-openssl` and `crypto-rust` features can't be enabled together");
+
+#[cfg(feature = "crypto-openssl")]
+mod openssl;
+#[cfg(feature = "crypto-openssl")]
+pub(crate) use self::openssl::{decode_base64, Hasher, PublicKey};
+
+#[cfg(feature = "crypto-rust")]
+mod rust;
+#[cfg(feature = "crypto-rust")]
+pub(crate) use self::rust::{decode_base64, Hasher, PublicKey};
@@ -0,0 +1,82 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{Scheme, VerifyError};
+use openssl::{
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{Id, PKey, Public},
+    sha::Sha256,
+    sign::Verifier,
+};
+
+#[derive(Clone)]
+pub(crate) struct PublicKey {
+    key: PKey<Public>,
+    scheme: Scheme,
+}
+
+impl PublicKey {
+    pub(crate) fn from_pem(pem: &[u8]) -> Result<Self, VerifyError> {
+        let key = PKey::public_key_from_pem(pem)
+            .map_err(|e| VerifyError::KeyUnavailable(e.to_string()))?;
+        let scheme = match key.id() {
+            Id::RSA => Scheme::RsaPkcs1Sha256,
+            Id::RSA_PSS => Scheme::RsaPssSha256,
+            Id::EC => {
+                let curve = key.ec_key().ok().and_then(|k| k.group().curve_name());
+                match curve {
+                    Some(Nid::X9_62_PRIME256V1) => Scheme::EcdsaP256Sha256,
+                    _ => {
+                        return Err(VerifyError::KeyUnavailable(String::from("unsupported curve")))
+                    }
+                }
+            }
+            Id::ED25519 => Scheme::Ed25519,
+            id => {
+                return Err(VerifyError::KeyUnavailable(format!("unsupported key type {:?}", id)))
+            }
+        };
+
+        Ok(PublicKey { key, scheme })
+    }
+
+    pub(crate) fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    pub(crate) fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        // PSS padding comes from the key itself, so both RSA schemes share
+        // the same setup
+        let verifier = match self.scheme {
+            Scheme::RsaPkcs1Sha256 | Scheme::RsaPssSha256 | Scheme::EcdsaP256Sha256 => {
+                Verifier::new(MessageDigest::sha256(), &self.key)
+            }
+            Scheme::Ed25519 => Verifier::new_without_digest(&self.key),
+        };
+        // A signature openssl is unable to even check is as bad as a wrong one
+        verifier.and_then(|mut ver| ver.verify_oneshot(signature, message)).unwrap_or_default()
+    }
+}
+
+/// SHA-256 digest computed incrementally.
+pub(crate) struct Hasher(Sha256);
+
+impl Hasher {
+    pub(crate) fn new() -> Self {
+        Hasher(Sha256::new())
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0.update(data)
+    }
+
+    pub(crate) fn finish(self) -> [u8; 32] {
+        self.0.finish()
+    }
+}
+
+pub(crate) fn decode_base64(content: &str) -> Result<Vec<u8>, String> {
+    openssl::base64::decode_block(content).map_err(|e| e.to_string())
+}
@@ -0,0 +1,106 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{Scheme, VerifyError};
+// The same `signature` trait is used by both ed25519-dalek and p256
+use ed25519_dalek::Verifier as _;
+use rsa::{pkcs1::DecodeRsaPublicKey, Pkcs1v15Sign, Pss, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use spki::{ObjectIdentifier, SubjectPublicKeyInfoRef};
+use std::convert::TryFrom;
+
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const RSASSA_PSS: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.10");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+#[derive(Clone)]
+pub(crate) enum PublicKey {
+    RsaPkcs1(RsaPublicKey),
+    RsaPss(RsaPublicKey),
+    EcdsaP256(p256::ecdsa::VerifyingKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+impl PublicKey {
+    pub(crate) fn from_pem(pem: &[u8]) -> Result<Self, VerifyError> {
+        let unavailable = |e: &dyn std::fmt::Display| VerifyError::KeyUnavailable(e.to_string());
+
+        let (_, der) = spki::der::pem::decode_vec(pem).map_err(|e| unavailable(&e))?;
+        let info =
+            SubjectPublicKeyInfoRef::try_from(der.as_slice()).map_err(|e| unavailable(&e))?;
+        let bits = info
+            .subject_public_key
+            .as_bytes()
+            .ok_or_else(|| VerifyError::KeyUnavailable(String::from("malformed key")))?;
+
+        let oid = info.algorithm.oid;
+        if oid == RSA_ENCRYPTION {
+            RsaPublicKey::from_pkcs1_der(bits).map(PublicKey::RsaPkcs1).map_err(|e| unavailable(&e))
+        } else if oid == RSASSA_PSS {
+            RsaPublicKey::from_pkcs1_der(bits).map(PublicKey::RsaPss).map_err(|e| unavailable(&e))
+        } else if oid == EC_PUBLIC_KEY {
+            if info.algorithm.parameters_oid().ok() != Some(SECP256R1) {
+                return Err(VerifyError::KeyUnavailable(String::from("unsupported curve")));
+            }
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(bits)
+                .map(PublicKey::EcdsaP256)
+                .map_err(|e| unavailable(&e))
+        } else if oid == ED25519 {
+            let bytes = <[u8; 32]>::try_from(bits).map_err(|e| unavailable(&e))?;
+            ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+                .map(PublicKey::Ed25519)
+                .map_err(|e| unavailable(&e))
+        } else {
+            Err(VerifyError::KeyUnavailable(format!("unsupported key type {}", oid)))
+        }
+    }
+
+    pub(crate) fn scheme(&self) -> Scheme {
+        match self {
+            PublicKey::RsaPkcs1(_) => Scheme::RsaPkcs1Sha256,
+            PublicKey::RsaPss(_) => Scheme::RsaPssSha256,
+            PublicKey::EcdsaP256(_) => Scheme::EcdsaP256Sha256,
+            PublicKey::Ed25519(_) => Scheme::Ed25519,
+        }
+    }
+
+    pub(crate) fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PublicKey::RsaPkcs1(key) => key
+                .verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(message), signature)
+                .is_ok(),
+            PublicKey::RsaPss(key) => {
+                key.verify(Pss::new::<Sha256>(), &Sha256::digest(message), signature).is_ok()
+            }
+            PublicKey::EcdsaP256(key) => p256::ecdsa::Signature::from_der(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+            PublicKey::Ed25519(key) => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+        }
+    }
+}
+
+/// SHA-256 digest computed incrementally.
+pub(crate) struct Hasher(Sha256);
+
+impl Hasher {
+    pub(crate) fn new() -> Self {
+        Hasher(Sha256::new())
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0.update(data)
+    }
+
+    pub(crate) fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+pub(crate) fn decode_base64(content: &str) -> Result<Vec<u8>, String> {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.decode(content).map_err(|e| e.to_string())
+}
@@ -0,0 +1,417 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{crypto::Hasher, Payload};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum DownloadError {
+    /// The remote answered the payload request with a status other than
+    /// `200 OK`, or `206 Partial Content` when resuming.
+    Status(u16),
+    /// The remote resumed the payload from another offset than the one asked.
+    InvalidRange,
+    /// The payload could not be written to its file.
+    Io(io::Error),
+    /// The payload received is not as long as the manifest says.
+    SizeMismatch { expected: u64, received: u64 },
+    /// The payload received doesn't have the digest the manifest says.
+    DigestMismatch,
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DownloadError::Status(status) => write!(f, "payload request failed with {}", status),
+            DownloadError::InvalidRange => write!(f, "payload resumed from the wrong offset"),
+            DownloadError::Io(e) => write!(f, "unable to write payload: {}", e),
+            DownloadError::SizeMismatch { expected, received } => {
+                write!(f, "expected a payload of {} bytes, received {}", expected, received)
+            }
+            DownloadError::DigestMismatch => write!(f, "payload digest mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// Destination of a payload being downloaded, which checks it against the
+/// manifest while it is written.
+///
+/// The payload is written to a `.part` file next to its final path, which is
+/// kept when the download is interrupted so the next attempt resumes from it.
+/// Remote clients send the [`range`](Self::range) header when there is one,
+/// hand the response's status to [`begin`](Self::begin) and then feed the body
+/// chunk by chunk, as it is received, returning the error given by
+/// [`write`](Self::write) as soon as there is one.
+pub struct PayloadWriter {
+    payload: Payload,
+    path: PathBuf,
+    part: PathBuf,
+    file: Option<File>,
+    hasher: Hasher,
+    received: u64,
+    io_error: Option<io::ErrorKind>,
+}
+
+impl PayloadWriter {
+    /// Picks up what was received by a previous attempt, if anything.
+    pub fn new(payload: Payload, path: PathBuf) -> Self {
+        let mut part = path.clone().into_os_string();
+        part.push(".part");
+        let mut writer = PayloadWriter {
+            payload,
+            path,
+            part: part.into(),
+            file: None,
+            hasher: Hasher::new(),
+            received: 0,
+            io_error: None,
+        };
+        if writer.resume().is_err() {
+            writer.discard();
+        }
+        writer
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    /// Value of the `range` header asking for the rest of the payload, when
+    /// part of it was already received.
+    pub fn range(&self) -> Option<String> {
+        if self.received > 0 {
+            Some(format!("bytes={}-", self.received))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the whole payload was already received, so there is nothing
+    /// left to request.
+    pub fn is_complete(&self) -> bool {
+        self.received == self.payload.size
+    }
+
+    /// Checks the status and `content-range` header of the response before
+    /// its body is written. A `200 OK` starts the payload over, as the remote
+    /// may not support ranges, while a `206 Partial Content` has to continue
+    /// it from where it stopped.
+    pub fn begin(
+        &mut self,
+        status: u16,
+        content_range: Option<&[u8]>,
+    ) -> Result<(), DownloadError> {
+        match status {
+            200 => {
+                self.discard();
+                Ok(())
+            }
+            206 if content_range.and_then(range_start) == Some(self.received) => Ok(()),
+            206 => {
+                self.discard();
+                Err(DownloadError::InvalidRange)
+            }
+            416 => {
+                // What was kept no longer matches the payload the remote has
+                self.discard();
+                Err(DownloadError::Status(status))
+            }
+            status => Err(DownloadError::Status(status)),
+        }
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) -> Result<(), DownloadError> {
+        self.received += chunk.len() as u64;
+        if self.received > self.payload.size {
+            return Err(self.size_mismatch());
+        }
+        self.hasher.update(chunk);
+        self.file().and_then(|file| file.write_all(chunk)).map_err(|e| {
+            self.io_error = Some(e.kind());
+            DownloadError::Io(e)
+        })
+    }
+
+    /// Whether the writer refused part of the payload, so the download was
+    /// interrupted by the agent and not by the remote.
+    pub fn is_rejected(&self) -> bool {
+        self.io_error.is_some() || self.received > self.payload.size
+    }
+
+    /// Checks the payload is whole, moving it to its final path. It is
+    /// removed if it is not, as it can't be resumed.
+    pub fn finish(mut self) -> Result<PathBuf, DownloadError> {
+        let checked = self.check();
+        drop(self.file.take());
+        match checked.and_then(|()| fs::rename(&self.part, &self.path).map_err(DownloadError::Io)) {
+            Ok(()) => Ok(self.path),
+            Err(e) => {
+                self.discard();
+                Err(e)
+            }
+        }
+    }
+
+    fn check(&mut self) -> Result<(), DownloadError> {
+        if let Some(kind) = self.io_error {
+            return Err(DownloadError::Io(kind.into()));
+        }
+        if self.received != self.payload.size {
+            return Err(self.size_mismatch());
+        }
+        self.file().and_then(|file| file.sync_all()).map_err(DownloadError::Io)?;
+
+        let hasher = std::mem::replace(&mut self.hasher, Hasher::new());
+        if hasher.finish() != self.payload.sha256 {
+            return Err(DownloadError::DigestMismatch);
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self) -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).append(true).open(&self.part)?;
+        if file.metadata()?.len() > self.payload.size {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let mut buf = [0; 16 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            self.hasher.update(&buf[..n]);
+            self.received += n as u64;
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn discard(&mut self) {
+        self.file = None;
+        self.hasher = Hasher::new();
+        self.received = 0;
+        self.io_error = None;
+        // There may be nothing to remove at all
+        let _ = fs::remove_file(&self.part);
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            self.file = Some(create(&self.part)?);
+        }
+        Ok(self.file.as_mut().expect("File was just created"))
+    }
+
+    fn size_mismatch(&self) -> DownloadError {
+        DownloadError::SizeMismatch { expected: self.payload.size, received: self.received }
+    }
+}
+
+fn create(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    File::create(path)
+}
+
+/// Start of the range in a `content-range` header, as in `bytes 100-199/200`.
+fn range_start(content_range: &[u8]) -> Option<u64> {
+    let range = std::str::from_utf8(content_range).ok()?.trim().strip_prefix("bytes ")?;
+    range.split('-').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] = b"The payload of the fooobarrr update, which is 64 bytes long.....";
+
+    /// Directory removed once the test is done with it.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "bench-download-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn writer(&self) -> PayloadWriter {
+            let mut hasher = Hasher::new();
+            hasher.update(DATA);
+            let payload = Payload {
+                size: DATA.len() as u64,
+                sha256: hasher.finish(),
+                url: String::from("/payloads/0.0.3"),
+            };
+            PayloadWriter::new(payload, self.path())
+        }
+
+        fn path(&self) -> PathBuf {
+            self.0.join("fooobarrr-0.0.3")
+        }
+
+        fn part(&self) -> PathBuf {
+            self.0.join("fooobarrr-0.0.3.part")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn finish_whole_payload() {
+        let dir = TempDir::new("whole");
+        let mut writer = dir.writer();
+        writer.begin(200, None).unwrap();
+        for chunk in DATA.chunks(10) {
+            writer.write(chunk).unwrap();
+        }
+        assert!(writer.is_complete());
+
+        assert_eq!(writer.finish().unwrap(), dir.path());
+        assert_eq!(fs::read(dir.path()).unwrap(), DATA);
+        assert!(!dir.part().exists());
+    }
+
+    #[test]
+    fn reject_oversized_payload() {
+        let dir = TempDir::new("oversized");
+        let mut writer = dir.writer();
+        writer.begin(200, None).unwrap();
+        writer.write(DATA).unwrap();
+        let e = writer.write(b"!").unwrap_err();
+        assert!(matches!(e, DownloadError::SizeMismatch { expected: 64, received: 65 }), "{}", e);
+        assert!(writer.is_rejected());
+
+        assert!(matches!(writer.finish(), Err(DownloadError::SizeMismatch { .. })));
+        assert!(!dir.part().exists());
+        assert!(!dir.path().exists());
+    }
+
+    #[test]
+    fn reject_truncated_payload() {
+        let dir = TempDir::new("truncated");
+        let mut writer = dir.writer();
+        writer.begin(200, None).unwrap();
+        writer.write(&DATA[..60]).unwrap();
+        assert!(!writer.is_rejected());
+
+        let e = writer.finish().unwrap_err();
+        assert!(matches!(e, DownloadError::SizeMismatch { expected: 64, received: 60 }), "{}", e);
+        assert!(!dir.part().exists());
+        assert!(!dir.path().exists());
+    }
+
+    #[test]
+    fn reject_digest_mismatch() {
+        let dir = TempDir::new("digest");
+        let mut writer = dir.writer();
+        writer.begin(200, None).unwrap();
+        let mut data = DATA.to_vec();
+        data[0] ^= 1;
+        writer.write(&data).unwrap();
+
+        assert!(matches!(writer.finish(), Err(DownloadError::DigestMismatch)));
+        assert!(!dir.part().exists());
+        assert!(!dir.path().exists());
+    }
+
+    /// Leaves the first `n` bytes of the payload behind, as an interrupted
+    /// download does.
+    fn interrupt_after(dir: &TempDir, n: usize) {
+        let mut writer = dir.writer();
+        writer.begin(200, None).unwrap();
+        writer.write(&DATA[..n]).unwrap();
+    }
+
+    #[test]
+    fn resume_matching_range() {
+        let dir = TempDir::new("resume");
+        interrupt_after(&dir, 20);
+
+        let mut writer = dir.writer();
+        assert_eq!(writer.range().as_deref(), Some("bytes=20-"));
+        writer.begin(206, Some(b"bytes 20-63/64")).unwrap();
+        writer.write(&DATA[20..]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(fs::read(dir.path()).unwrap(), DATA);
+    }
+
+    #[test]
+    fn reject_mismatched_range() {
+        let dir = TempDir::new("mismatched-range");
+        for content_range in [Some(&b"bytes 10-63/64"[..]), Some(b"bytes */64"), None] {
+            interrupt_after(&dir, 20);
+            let mut writer = dir.writer();
+            assert!(matches!(writer.begin(206, content_range), Err(DownloadError::InvalidRange)));
+            assert!(!dir.part().exists());
+            assert_eq!(writer.range(), None);
+        }
+    }
+
+    #[test]
+    fn restart_on_whole_payload() {
+        let dir = TempDir::new("restart");
+        interrupt_after(&dir, 20);
+
+        let mut writer = dir.writer();
+        assert!(writer.range().is_some());
+        writer.begin(200, None).unwrap();
+        writer.write(DATA).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(fs::read(dir.path()).unwrap(), DATA);
+    }
+
+    #[test]
+    fn discard_unsatisfiable_range() {
+        let dir = TempDir::new("unsatisfiable");
+        interrupt_after(&dir, 20);
+
+        let mut writer = dir.writer();
+        assert!(matches!(writer.begin(416, Some(b"bytes */64")), Err(DownloadError::Status(416))));
+        assert!(!dir.part().exists());
+        assert_eq!(writer.range(), None);
+    }
+
+    #[test]
+    fn keep_part_on_other_status() {
+        let dir = TempDir::new("other-status");
+        interrupt_after(&dir, 20);
+
+        let mut writer = dir.writer();
+        assert!(matches!(writer.begin(503, None), Err(DownloadError::Status(503))));
+        assert_eq!(fs::metadata(dir.part()).unwrap().len(), 20);
+        assert_eq!(writer.range().as_deref(), Some("bytes=20-"));
+    }
+
+    #[test]
+    fn discard_oversized_part() {
+        let dir = TempDir::new("oversized-part");
+        let mut part = DATA.to_vec();
+        part.push(b'!');
+        fs::write(dir.part(), part).unwrap();
+
+        let writer = dir.writer();
+        assert_eq!(writer.range(), None);
+        assert!(!writer.is_complete());
+        assert!(!dir.part().exists());
+    }
+}
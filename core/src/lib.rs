@@ -0,0 +1,471 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use agent::Clock;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, net::SocketAddr, path::PathBuf, time::Duration};
+
+pub use download::{DownloadError, PayloadWriter};
+pub use package::{Package, Payload, MANIFEST_VERSION};
+pub use policy::Decision;
+pub use report::{Outcome, Report, ReportError};
+pub use semver::Version;
+pub use serde_json::Error as ParsingError;
+pub use state::{StateError, StateStore};
+pub use verifier::{Scheme, Verifier, VerifyError};
+
+pub mod agent;
+mod crypto;
+mod download;
+mod package;
+mod policy;
+mod report;
+mod state;
+mod verifier;
+
+pub mod prelude {
+    pub use super::{AppImpl, LocalClientImpl, RemoteClientImpl};
+}
+
+#[async_trait::async_trait(?Send)]
+pub trait LocalClientImpl: Sized {
+    type Err: std::fmt::Debug;
+    fn new(server: SocketAddr) -> Self;
+    async fn fetch_info(&mut self) -> Result<Info, Self::Err>;
+}
+
+#[async_trait::async_trait(?Send)]
+pub trait RemoteClientImpl: Sized {
+    type Err: std::fmt::Debug;
+
+    /// Creates the client for the remote at `url`, which is served over TLS
+    /// with a certificate issued by `ca`. Every request it makes is bound by
+    /// the timeouts of `policy`. Fails when the client can't be set up, as
+    /// when `ca` isn't a valid certificate.
+    fn new(url: &str, ca: &CaCertificate, policy: &RequestPolicy) -> Result<Self, Self::Err>;
+    /// Whether the request failed before the remote answered it, as when it
+    /// timed out or the connection was reset, so it is worth retrying.
+    fn is_transient(err: &Self::Err) -> bool;
+    /// Fetches the package body as sent by the remote, along with its
+    /// signature. The body is only parsed by the agent once the signature is
+    /// verified. The request carries the conditional headers of `validators`,
+    /// taken from the last package the agent handled.
+    async fn fetch_package(&mut self, validators: &Validators) -> Result<Fetched, Self::Err>;
+    /// Streams the payload described by `writer` to it, resuming it with the
+    /// `range` header given by the writer, if any. See [`PayloadWriter`] for
+    /// how the response is handed to it.
+    async fn download_payload(&mut self, writer: &mut PayloadWriter) -> Result<(), Self::Err>;
+    /// Sends the report as JSON in a `POST` to [`Report::url_from`] the
+    /// remote, checking the answer with [`Report::accepted`].
+    async fn report(&mut self, report: &Report) -> Result<(), Self::Err>;
+}
+
+#[async_trait::async_trait(?Send)]
+pub trait AppImpl: Sized {
+    type RemoteClient: RemoteClientImpl;
+    type Err: From<<Self::RemoteClient as RemoteClientImpl>::Err>
+        + From<DownloadError>
+        + From<StateError>
+        + std::fmt::Debug;
+
+    /// Creates the app, starting from the `Info` loaded from
+    /// [`Config::state`], which fails when there is one that can't be read.
+    fn new(client: Self::RemoteClient, config: Config) -> Result<Self, Self::Err>;
+    /// Starts the local server in the background on `addr`, calling
+    /// [`Ready::notify`] with the address it is bound to once it is accepting
+    /// connections. The port may be `0`, leaving the choice to the system.
+    fn serve(&mut self, addr: SocketAddr, ready: Ready) -> Result<(), Self::Err>;
+
+    /// Runs `f` over the `Info` held in memory. Changes meant to last go
+    /// through [`update_info`](Self::update_info) instead.
+    async fn map_info<F: FnOnce(&mut Info)>(&mut self, f: F) -> Result<(), Self::Err>;
+    async fn client(&mut self) -> Result<&mut Self::RemoteClient, Self::Err>;
+    fn config(&self) -> &Config;
+
+    /// Changes the `Info` and saves it, while it is still locked, so saves
+    /// happen in the same order as the changes.
+    async fn update_info<F: FnOnce(&mut Info)>(&mut self, f: F) -> Result<(), Self::Err> {
+        let state = self.config().state.clone();
+        let mut saved = Ok(());
+        self.map_info(|info| {
+            f(info);
+            saved = state.save(info);
+        })
+        .await?;
+        Ok(saved?)
+    }
+
+    /// Polls the remote once, handling the package it answers with, if any.
+    /// Retries wait on `clock`, as the rest of the agent does.
+    async fn process<C: Clock>(&mut self, clock: &C) -> Result<(), Self::Err> {
+        // A remote misbehaving, or not answering, is part of what the agent
+        // has to live with, so it is accounted for instead of failing
+        let fetched = self.fetch(clock).await?;
+        match fetched {
+            Err(_) => self.update_info(|info| info.count_remote_errors += 1).await?,
+            Ok(Fetched::Nothing) => {}
+            Ok(Fetched::NotModified) => {
+                self.update_info(|info| info.count_not_modified += 1).await?
+            }
+            Ok(Fetched::Package { body: raw, signature: sig, validators }) => {
+                let report = self.handle(&raw, &sig).await?;
+                // Only kept once the package was handled, so the remote isn't
+                // asked for it again unless it changes, while a package that
+                // failed to be handled, or whose payload has to be resumed,
+                // is sent again
+                let validators =
+                    Some(validators).filter(|_| report.outcome != Outcome::Interrupted);
+                self.update_info(|info| info.validators = validators).await?;
+                if self.client().await?.report(&report).await.is_err() {
+                    self.update_info(|info| info.count_remote_errors += 1).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the package, installing it when it is meant to be, and tells
+    /// what was done with it.
+    async fn handle(&mut self, raw: &[u8], sig: &Signature) -> Result<Report, Self::Err> {
+        if let Err(e) = self.config().verifier.verify(raw, sig) {
+            self.update_info(|info| info.count_invalid_packages += 1).await?;
+            return Ok(Report {
+                version: None,
+                outcome: Outcome::InvalidSignature,
+                error: Some(e.to_string()),
+            });
+        }
+        let pkg = match Package::parse(raw) {
+            Ok(pkg) => pkg,
+            Err(e) => {
+                self.update_info(|info| info.count_malformed_packages += 1).await?;
+                return Ok(Report {
+                    version: None,
+                    outcome: Outcome::Malformed,
+                    error: Some(e.to_string()),
+                });
+            }
+        };
+
+        let version = Some(pkg.version.clone());
+        if pkg.product_uid != self.config().identity.product_uid {
+            self.update_info(|info| info.count_foreign_packages += 1).await?;
+            let error = format!("package is built for {}", pkg.product_uid);
+            return Ok(Report { version, outcome: Outcome::Foreign, error: Some(error) });
+        }
+        let mut decision = Decision::Install;
+        self.map_info(|info| decision = Decision::new(&info.current_version, &pkg)).await?;
+        let (outcome, error) = match decision {
+            Decision::Install => return self.install(pkg).await,
+            Decision::SameVersion => {
+                self.update_info(|info| info.count_skipped_same_version += 1).await?;
+                (Outcome::SkippedSameVersion, None)
+            }
+            Decision::Downgrade => {
+                self.update_info(|info| info.count_skipped_downgrades += 1).await?;
+                (Outcome::SkippedDowngrade, None)
+            }
+            Decision::BelowMinimum => {
+                self.update_info(|info| info.count_skipped_below_minimum += 1).await?;
+                let min = pkg.min_required_version.as_ref();
+                let error = min.map(|min| format!("package requires {} or newer", min));
+                (Outcome::SkippedBelowMinimum, error)
+            }
+        };
+        Ok(Report { version, outcome, error })
+    }
+
+    /// Fetches the package, retrying as set by the [`RequestPolicy`] while
+    /// the remote doesn't answer. Payloads aren't retried this way, as the
+    /// next poll resumes them.
+    async fn fetch<C: Clock>(
+        &mut self,
+        clock: &C,
+    ) -> Result<Result<Fetched, <Self::RemoteClient as RemoteClientImpl>::Err>, Self::Err> {
+        let policy = self.config().request.clone();
+        let mut validators = None;
+        self.map_info(|info| validators = info.validators.clone()).await?;
+        let validators = validators.unwrap_or_default();
+        let mut attempt = 0;
+        loop {
+            let fetched = self.client().await?.fetch_package(&validators).await;
+            match fetched {
+                Err(e) if attempt < policy.retries && Self::RemoteClient::is_transient(&e) => {
+                    attempt += 1;
+                    clock.sleep(policy.retry_delay).await;
+                }
+                fetched => return Ok(fetched),
+            }
+        }
+    }
+
+    /// Downloads the payload of the package, if it has one, and only takes
+    /// the package's version as the running one once the payload is checked.
+    async fn install(&mut self, pkg: Package) -> Result<Report, Self::Err> {
+        let version = Some(pkg.version.clone());
+        if let Some(payload) = pkg.payload {
+            let name = format!("{}-{}", pkg.product_uid, pkg.version);
+            let mut writer = PayloadWriter::new(payload, self.config().download_dir.join(name));
+            let downloaded = if writer.is_complete() {
+                Ok(())
+            } else {
+                self.client().await?.download_payload(&mut writer).await
+            };
+            match downloaded {
+                Err(_) if !writer.is_rejected() => {
+                    // What was received is kept for the next attempt to resume
+                    self.update_info(|info| info.count_remote_errors += 1).await?;
+                    // The client's error is left out, as it differs between
+                    // contestants while reports have to be the same
+                    let error = Some(String::from("payload download interrupted"));
+                    return Ok(Report { version, outcome: Outcome::Interrupted, error });
+                }
+                _ => {}
+            }
+            match writer.finish() {
+                Ok(_) => {}
+                // Being unable to store the payload is the agent's own failure
+                Err(DownloadError::Io(e)) => return Err(DownloadError::Io(e).into()),
+                Err(e) => {
+                    self.update_info(|info| info.count_invalid_payloads += 1).await?;
+                    let error = Some(e.to_string());
+                    return Ok(Report { version, outcome: Outcome::InvalidPayload, error });
+                }
+            }
+        }
+
+        let installed = pkg.version;
+        self.update_info(move |info| info.current_version = installed).await?;
+        Ok(Report { version, outcome: Outcome::Installed, error: None })
+    }
+}
+
+/// Settings the agent is built with, which don't change while it runs.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub verifier: Verifier,
+    pub identity: Identity,
+    /// Directory payloads are downloaded to.
+    pub download_dir: PathBuf,
+    /// Where the `Info` is kept between runs, loaded by [`AppImpl::new`].
+    pub state: StateStore,
+    pub polling: agent::Polling,
+    pub request: RequestPolicy,
+}
+
+/// Bounds on the requests made to the remote, so one that stops answering
+/// doesn't hold the agent forever.
+#[derive(Clone, Debug)]
+pub struct RequestPolicy {
+    /// Longest time to establish the connection to the remote.
+    pub connect_timeout: Duration,
+    /// Longest time for a whole request, from connecting to the end of the
+    /// response's body.
+    pub timeout: Duration,
+    /// Times a package request is retried after a transient failure, within
+    /// the same poll.
+    pub retries: u32,
+    /// Wait before each retry.
+    pub retry_delay: Duration,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        RequestPolicy {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(5 * 60),
+            retries: 2,
+            retry_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Identifies the device the agent runs on, so only packages meant for it
+/// are installed.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Identity {
+    /// Product packages must be built for.
+    pub product_uid: String,
+    /// Further details about the device, such as its hardware revision.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl Identity {
+    pub fn parse(content: &[u8]) -> Result<Self, ParsingError> {
+        serde_json::from_slice(content)
+    }
+}
+
+/// Signal used by [`AppImpl::serve`] to tell when the local server is bound.
+/// Dropping it without notifying tells the waiting side that the server failed
+/// to start.
+pub struct Ready(futures_channel::oneshot::Sender<SocketAddr>);
+
+impl Ready {
+    pub fn new() -> (Self, futures_channel::oneshot::Receiver<SocketAddr>) {
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        (Ready(sender), receiver)
+    }
+
+    pub fn notify(self, bound: SocketAddr) {
+        // Nobody waiting for the server is not an error for the server itself
+        let _ = self.0.send(bound);
+    }
+}
+
+/// Certificate authority the remote server's certificate is issued by, which
+/// the remote client has to trust. Clients that can be given extra
+/// certificates trust it on top of the system's ones, while those that can only
+/// read `SSL_CERT_FILE` trust it alone.
+#[derive(Clone, Debug)]
+pub struct CaCertificate {
+    /// Location of the certificate, for TLS stacks that can only be pointed to
+    /// a file.
+    pub path: PathBuf,
+    /// The certificate itself, PEM encoded.
+    pub pem: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Signature(pub Vec<u8>);
+
+impl Signature {
+    pub const INVALID_SAMPLE: &'static str = r#"Hx6kv5dndxA/3qi9QAgXlaiyCrhKZLE7TLXVHVIU9XNq0qIyuRWCDaBDSXCbFKTgd26gBY6q30FHpxrDuf09UPnznluxv/0LbGbwyyskj4c5CZwQIGCcj+5a+ypV68G7hzFsaY3l7COvtGfQPnFT3B7JovqoLTpNgh/VtI0PHDo="#;
+    /// Get a valid signature. Static signature generated with:
+    /// ```shell
+    /// echo -n '{"product":"fooobarrr","version":"0.0.2"}' | \
+    ///   openssl dgst -sha256 -sign fixtures/ssh/key | base64
+    /// ```
+    pub const VALID_SAMPLE: &'static str = r#"xcPhKCRaL3YheiVvJOhypjFKW7e8sJzyIve2k+Higp+BtB5ED31rW3wl/noDqvIA7YVyWVnEE/nzRfRrjNOE1ylbxwUuOsjRamCr2y6C8q7rBshA6msRmwsVAmIKHcjGWhL/p1bF9WjS7vNbItx0ujHuDlqgTwutvM9XN702IjE="#;
+
+    pub fn from_base64_str(content: &str) -> Result<Self, SignatureError> {
+        crypto::decode_base64(content).map(Signature).map_err(SignatureError::Base64)
+    }
+
+    /// Decodes the value of the `signature` header, as found in the response
+    /// headers, if any.
+    pub fn from_header(value: Option<&[u8]>) -> Result<Self, SignatureError> {
+        let value = value.ok_or(SignatureError::Missing)?;
+        let value = std::str::from_utf8(value)
+            .ok()
+            .filter(|value| value.is_ascii())
+            .ok_or(SignatureError::NonAscii)?;
+        Self::from_base64_str(value)
+    }
+}
+
+/// What the remote answered to a package request.
+#[derive(Debug)]
+pub enum Fetched {
+    /// The remote has no package for the agent.
+    Nothing,
+    /// The remote answered `304 Not Modified`, as the package is still the
+    /// one the validators sent were taken from.
+    NotModified,
+    Package {
+        body: Vec<u8>,
+        signature: Signature,
+        validators: Validators,
+    },
+}
+
+/// Identifies a version of the package on the remote, as given by the `etag`
+/// and `last-modified` headers, so it is only sent again once it changes.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Takes the validators from the response headers, ignoring values that
+    /// are not valid UTF-8.
+    pub fn from_headers(etag: Option<&[u8]>, last_modified: Option<&[u8]>) -> Self {
+        let value = |value: Option<&[u8]>| {
+            value.and_then(|value| std::str::from_utf8(value).ok()).map(str::to_owned)
+        };
+        Validators { etag: value(etag), last_modified: value(last_modified) }
+    }
+
+    /// Conditional headers the next package request has to carry.
+    pub fn headers(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        let etag = self.etag.as_deref().map(|etag| ("if-none-match", etag));
+        let last_modified = self.last_modified.as_deref().map(|date| ("if-modified-since", date));
+        etag.into_iter().chain(last_modified)
+    }
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The response has no `signature` header.
+    Missing,
+    /// The header has characters other than ASCII ones.
+    NonAscii,
+    /// The header is not valid base64.
+    Base64(String),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "missing signature header"),
+            SignatureError::NonAscii => write!(f, "signature header is not ASCII"),
+            SignatureError::Base64(e) => write!(f, "signature is not valid base64: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+#[derive(Clone, Deserialize, Debug, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Info {
+    pub current_version: Version,
+    pub count_invalid_packages: u32,
+    /// Packages signed correctly but which could not be parsed.
+    pub count_malformed_packages: u32,
+    /// Requests to the remote that failed, including responses without a
+    /// usable signature.
+    pub count_remote_errors: u32,
+    /// Packages skipped for having the version already running.
+    pub count_skipped_same_version: u32,
+    /// Packages skipped for being older than the running version without being
+    /// marked as a rollback.
+    pub count_skipped_downgrades: u32,
+    /// Packages skipped for requiring a newer version than the running one to
+    /// be installed over.
+    pub count_skipped_below_minimum: u32,
+    /// Packages signed correctly but built for another product.
+    pub count_foreign_packages: u32,
+    /// Payloads downloaded whose size or digest don't match their manifest.
+    pub count_invalid_payloads: u32,
+    /// Package requests the remote answered with `304 Not Modified`, sparing
+    /// the package's download.
+    pub count_not_modified: u32,
+    /// Unix time, in seconds, the [`agent`] polls the remote next.
+    pub next_poll: Option<u64>,
+    /// Validators of the last package handled.
+    pub validators: Option<Validators>,
+}
+
+impl Default for Info {
+    fn default() -> Self {
+        Info {
+            current_version: Version::new(0, 0, 1),
+            count_invalid_packages: 0,
+            count_malformed_packages: 0,
+            count_remote_errors: 0,
+            count_skipped_same_version: 0,
+            count_skipped_downgrades: 0,
+            count_skipped_below_minimum: 0,
+            count_foreign_packages: 0,
+            count_invalid_payloads: 0,
+            count_not_modified: 0,
+            next_poll: None,
+            validators: None,
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The update manifest, as served by the remote. The signature covers the
+//! manifest as a whole, so `raw` is kept next to the parsed fields.
+//!
+//! Manifests without a `manifest_version` are read as version `1`, which only
+//! has the `product` and `version` fields, plus the optional `rollback` flag,
+//! and is rejected when it has any of the fields of later versions:
+//!
+//! ```json
+//! {"product":"fooobarrr","version":"0.0.2"}
+//! ```
+//!
+//! Version `2` describes the payload to be downloaded as well:
+//!
+//! ```json
+//! {
+//!   "manifest_version": 2,
+//!   "product": "fooobarrr",
+//!   "version": "0.0.3",
+//!   "min_required_version": "0.0.1",
+//!   "release_notes": "Fixes the fooing of bars",
+//!   "payload": {
+//!     "size": 1024,
+//!     "sha256": "5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef",
+//!     "url": "/payloads/0.0.3"
+//!   }
+//! }
+//! ```
+
+use crate::ParsingError;
+use semver::Version;
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+/// Latest manifest version understood by the agent.
+pub const MANIFEST_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub struct Package {
+    /// Format the manifest was written in.
+    pub manifest_version: u32,
+    pub product_uid: String,
+    pub version: Version,
+    /// Allows the package to replace a newer version.
+    pub rollback: bool,
+    /// Oldest version the package can be installed over.
+    pub min_required_version: Option<Version>,
+    pub release_notes: Option<String>,
+    /// Only described by manifests from version `2` on.
+    pub payload: Option<Payload>,
+    /// The manifest as served, which is what the signature is checked against.
+    pub raw: Vec<u8>,
+}
+
+/// Image to be downloaded to apply the package.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Payload {
+    pub size: u64,
+    #[serde(deserialize_with = "sha256_from_hex")]
+    pub sha256: [u8; 32],
+    /// Where the payload is downloaded from, relative to the remote's URL
+    /// unless absolute.
+    pub url: String,
+}
+
+impl Payload {
+    /// Where to download the payload from, given the URL of the remote the
+    /// manifest was fetched from.
+    pub fn url_from(&self, remote: &str) -> String {
+        if self.url.contains("://") {
+            return self.url.clone();
+        }
+        format!("{}/{}", remote.trim_end_matches('/'), self.url.trim_start_matches('/'))
+    }
+}
+
+impl Default for Package {
+    fn default() -> Self {
+        Package {
+            manifest_version: 1,
+            product_uid: String::from("fooobarrr"),
+            version: Version::new(0, 0, 2),
+            rollback: false,
+            min_required_version: None,
+            release_notes: None,
+            payload: None,
+            raw: br#"{"product":"fooobarrr","version":"0.0.2"}"#.to_vec(),
+        }
+    }
+}
+
+impl Package {
+    pub fn parse(content: &[u8]) -> Result<Self, ParsingError> {
+        #[derive(Deserialize)]
+        struct PackageAux {
+            #[serde(default = "PackageAux::first_version")]
+            manifest_version: u32,
+            #[serde(rename = "product")]
+            product_uid: String,
+            version: Version,
+            #[serde(default)]
+            rollback: bool,
+            min_required_version: Option<Version>,
+            release_notes: Option<String>,
+            payload: Option<Payload>,
+        }
+
+        impl PackageAux {
+            fn first_version() -> u32 {
+                1
+            }
+        }
+
+        let update_package = serde_json::from_slice::<PackageAux>(content)?;
+        match update_package.manifest_version {
+            1 => {
+                let later_fields = [
+                    ("min_required_version", update_package.min_required_version.is_some()),
+                    ("release_notes", update_package.release_notes.is_some()),
+                    ("payload", update_package.payload.is_some()),
+                ];
+                if let Some((field, _)) = later_fields.iter().find(|(_, given)| *given) {
+                    return Err(ParsingError::custom(format!(
+                        "{} is not part of manifest version 1",
+                        field
+                    )));
+                }
+            }
+            2 if update_package.payload.is_none() => {
+                return Err(ParsingError::custom("manifest has no payload"))
+            }
+            2 => {}
+            version => {
+                return Err(ParsingError::custom(format!(
+                    "unsupported manifest version {}, expected up to {}",
+                    version, MANIFEST_VERSION
+                )))
+            }
+        }
+
+        Ok(Package {
+            manifest_version: update_package.manifest_version,
+            product_uid: update_package.product_uid,
+            version: update_package.version,
+            rollback: update_package.rollback,
+            min_required_version: update_package.min_required_version,
+            release_notes: update_package.release_notes,
+            payload: update_package.payload,
+            raw: content.to_vec(),
+        })
+    }
+}
+
+fn sha256_from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+    let hex = <&str>::deserialize(deserializer)?;
+    let invalid = || D::Error::custom("sha256 must have 64 hexadecimal digits");
+    let digit = |c: u8| char::from(c).to_digit(16).ok_or_else(invalid);
+
+    let mut digest = [0; 32];
+    if hex.len() != digest.len() * 2 {
+        return Err(invalid());
+    }
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = (digit(pair[0])? << 4 | digit(pair[1])?) as u8;
+    }
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &str = r#"{"size":1024,"sha256":"5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef","url":"/payloads/0.0.3"}"#;
+
+    fn manifest(fields: &str) -> Vec<u8> {
+        format!(r#"{{"product":"fooobarrr","version":"0.0.3"{}}}"#, fields).into_bytes()
+    }
+
+    #[test]
+    fn parse_first_version() {
+        let default = Package::default();
+        let pkg = Package::parse(&default.raw).unwrap();
+        assert_eq!(pkg.manifest_version, 1);
+        assert_eq!(pkg.product_uid, default.product_uid);
+        assert_eq!(pkg.version, default.version);
+        assert!(!pkg.rollback);
+        assert_eq!(pkg.payload, None);
+        assert_eq!(pkg.raw, default.raw);
+
+        assert!(Package::parse(&manifest(r#","rollback":true"#)).unwrap().rollback);
+    }
+
+    #[test]
+    fn reject_later_fields_in_first_version() {
+        for fields in [
+            r#","min_required_version":"0.0.1""#.to_owned(),
+            r#","release_notes":"Fixes the fooing of bars""#.to_owned(),
+            format!(r#","payload":{}"#, PAYLOAD),
+            format!(r#","manifest_version":1,"payload":{}"#, PAYLOAD),
+        ] {
+            assert!(Package::parse(&manifest(&fields)).is_err(), "{}", fields);
+        }
+    }
+
+    #[test]
+    fn parse_second_version() {
+        let fields = format!(
+            r#","manifest_version":2,"min_required_version":"0.0.1","release_notes":"Notes","payload":{}"#,
+            PAYLOAD
+        );
+        let pkg = Package::parse(&manifest(&fields)).unwrap();
+        assert_eq!(pkg.manifest_version, 2);
+        assert_eq!(pkg.min_required_version, Some(Version::new(0, 0, 1)));
+        assert_eq!(pkg.release_notes.as_deref(), Some("Notes"));
+        let payload = pkg.payload.unwrap();
+        assert_eq!(payload.size, 1024);
+        assert_eq!(payload.sha256[..4], [0x5f, 0x70, 0xbf, 0x18]);
+        assert_eq!(payload.sha256[31], 0xef);
+        assert_eq!(payload.url_from("https://remote/"), "https://remote/payloads/0.0.3");
+
+        assert!(Package::parse(&manifest(r#","manifest_version":2"#)).is_err());
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let fields = format!(r#","manifest_version":3,"payload":{}"#, PAYLOAD);
+        let e = Package::parse(&manifest(&fields)).unwrap_err();
+        assert!(e.to_string().contains("unsupported manifest version 3"), "{}", e);
+    }
+
+    #[test]
+    fn reject_invalid_digest() {
+        let digest = "5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef";
+        for sha256 in [&digest[..62], "5f70bf18", &digest.replace('f', "g")] {
+            let payload = PAYLOAD.replace(digest, sha256);
+            let fields = format!(r#","manifest_version":2,"payload":{}"#, payload);
+            assert!(Package::parse(&manifest(&fields)).is_err(), "{}", sha256);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::Package;
+use semver::Version;
+use std::cmp::Ordering;
+
+/// What the agent does with a package that is validly signed and well formed,
+/// given the version it is running.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decision {
+    /// The package is newer, or an older one explicitly marked as a rollback.
+    Install,
+    /// The package has the version already running, so there is nothing to do.
+    SameVersion,
+    /// The package is older and not marked as a rollback.
+    Downgrade,
+    /// The package would be installed, but the running version is older than
+    /// its `min_required_version`.
+    BelowMinimum,
+}
+
+impl Decision {
+    /// Versions are compared by semver precedence, so build metadata is not
+    /// taken into account.
+    pub fn new(current: &Version, pkg: &Package) -> Self {
+        let below_minimum = pkg
+            .min_required_version
+            .as_ref()
+            .is_some_and(|min| current.cmp_precedence(min) == Ordering::Less);
+        match pkg.version.cmp_precedence(current) {
+            Ordering::Equal => Decision::SameVersion,
+            Ordering::Less if !pkg.rollback => Decision::Downgrade,
+            _ if below_minimum => Decision::BelowMinimum,
+            _ => Decision::Install,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide(current: &str, version: &str, rollback: bool, min: Option<&str>) -> Decision {
+        let pkg = Package {
+            version: version.parse().unwrap(),
+            rollback,
+            min_required_version: min.map(|min| min.parse().unwrap()),
+            ..Package::default()
+        };
+        Decision::new(&current.parse().unwrap(), &pkg)
+    }
+
+    #[test]
+    fn versions() {
+        let cases = [
+            ("0.0.2", "0.0.3", false, None, Decision::Install),
+            ("0.0.2", "0.1.0", false, None, Decision::Install),
+            ("0.0.2", "0.0.2", false, None, Decision::SameVersion),
+            ("0.0.2", "0.0.2", true, None, Decision::SameVersion),
+            ("0.0.3", "0.0.2", false, None, Decision::Downgrade),
+            ("0.0.3", "0.0.2", true, None, Decision::Install),
+            // Pre-releases come before their release
+            ("0.0.2", "0.0.3-rc.1", false, None, Decision::Install),
+            ("0.0.3-rc.1", "0.0.3", false, None, Decision::Install),
+            ("0.0.3-rc.1", "0.0.3-rc.2", false, None, Decision::Install),
+            ("0.0.3", "0.0.3-rc.1", false, None, Decision::Downgrade),
+            // Build metadata has no precedence
+            ("0.0.2", "0.0.2+build.5", false, None, Decision::SameVersion),
+            ("0.0.2+build.5", "0.0.2+build.4", false, None, Decision::SameVersion),
+            ("0.0.2+build.5", "0.0.3+build.1", false, None, Decision::Install),
+            // The minimum is only checked for packages that would be installed
+            ("0.0.2", "0.0.4", false, Some("0.0.3"), Decision::BelowMinimum),
+            ("0.0.3", "0.0.4", false, Some("0.0.3"), Decision::Install),
+            ("0.0.3-rc.1", "0.0.4", false, Some("0.0.3"), Decision::BelowMinimum),
+            ("0.0.3+build.1", "0.0.4", false, Some("0.0.3"), Decision::Install),
+            ("0.0.3", "0.0.2", true, Some("0.0.4"), Decision::BelowMinimum),
+            ("0.0.3", "0.0.2", false, Some("0.0.4"), Decision::Downgrade),
+            ("0.0.3", "0.0.3", false, Some("0.0.4"), Decision::SameVersion),
+        ];
+        for (current, version, rollback, min, expected) in cases {
+            assert_eq!(
+                decide(current, version, rollback, min),
+                expected,
+                "{} to {}, rollback: {}, min: {:?}",
+                current,
+                version,
+                rollback,
+                min
+            );
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What the agent did with a package it fetched, sent back to the remote as
+/// JSON.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Report {
+    /// Version of the package, unless it couldn't be parsed.
+    pub version: Option<Version>,
+    pub outcome: Outcome,
+    /// Why the package was rejected, or not installed, if it wasn't.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Installed,
+    InvalidSignature,
+    Malformed,
+    /// The package is built for another product.
+    Foreign,
+    SkippedSameVersion,
+    SkippedDowngrade,
+    /// The running version is older than the package's
+    /// `min_required_version`.
+    SkippedBelowMinimum,
+    /// The payload's size or digest don't match the manifest.
+    InvalidPayload,
+    /// The payload's download was interrupted, and is resumed by the next
+    /// poll.
+    Interrupted,
+}
+
+impl Report {
+    /// Path reports are sent to, relative to the remote's URL.
+    pub const PATH: &'static str = "report";
+
+    /// Where to send the report, given the URL of the remote the package was
+    /// fetched from.
+    pub fn url_from(remote: &str) -> String {
+        format!("{}/{}", remote.trim_end_matches('/'), Self::PATH)
+    }
+
+    /// Checks the status the remote answered the report with, which has to be
+    /// a successful one.
+    pub fn accepted(status: u16) -> Result<(), ReportError> {
+        match status {
+            200..=299 => Ok(()),
+            status => Err(ReportError(status)),
+        }
+    }
+}
+
+/// The remote refused the report, with the status it answered.
+#[derive(Debug)]
+pub struct ReportError(pub u16);
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "report refused with {}", self.0)
+    }
+}
+
+impl std::error::Error for ReportError {}
@@ -0,0 +1,72 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::Info;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub struct StateError(pub io::Error);
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unable to load or save state: {}", self.0)
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Keeps the `Info` on disk, so it survives the agent being restarted.
+#[derive(Clone, Debug)]
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    pub fn new(path: PathBuf) -> Self {
+        StateStore { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the saved `Info`, starting over from the default one when there
+    /// is none yet. A state that can't be read or parsed is an error, as
+    /// starting over would overwrite it.
+    pub fn load(&self) -> Result<Info, StateError> {
+        match fs::read(&self.path) {
+            Ok(content) => serde_json::from_slice(&content).map_err(|e| StateError(e.into())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Info::default()),
+            Err(e) => Err(StateError(e)),
+        }
+    }
+
+    /// Saves the `Info` atomically, so a crash at any point leaves either the
+    /// previous state or the new one.
+    pub fn save(&self, info: &Info) -> Result<(), StateError> {
+        self.write(info).map_err(StateError)
+    }
+
+    fn write(&self, info: &Info) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        if let Some(dir) = dir {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(info).expect("Info is always serializable"))?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, &self.path)?;
+        // The rename itself is only durable once the directory is synced
+        File::open(dir.unwrap_or_else(|| Path::new(".")))?.sync_all()
+    }
+}
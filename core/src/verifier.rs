@@ -0,0 +1,152 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{crypto::PublicKey, Signature};
+use std::{fmt, path::Path};
+
+/// Signature algorithms packages can be signed with. The one in use is picked
+/// from the type of the trusted key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scheme {
+    /// RSA with PKCS#1 v1.5 padding over a SHA-256 digest.
+    RsaPkcs1Sha256,
+    /// RSA-PSS over a SHA-256 digest with a salt as long as the digest, for
+    /// keys restricted to PSS.
+    RsaPssSha256,
+    /// ECDSA on the P-256 curve over a SHA-256 digest, DER encoded.
+    EcdsaP256Sha256,
+    /// Ed25519 over the package itself.
+    Ed25519,
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The trusted key could not be read or is not a valid public key.
+    KeyUnavailable(String),
+    /// The signature was not made by the trusted key over the data.
+    Mismatch,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::KeyUnavailable(e) => write!(f, "trusted key unavailable: {}", e),
+            VerifyError::Mismatch => write!(f, "signature mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Checks package signatures against the trusted public key, which is loaded
+/// once when the verifier is built.
+#[derive(Clone)]
+pub struct Verifier {
+    key: PublicKey,
+}
+
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Verifier").field("scheme", &self.scheme()).finish()
+    }
+}
+
+impl Verifier {
+    /// Key the fixture packages are signed with, built into the binary.
+    pub const EMBEDDED_KEY: &'static [u8] = include_bytes!("../../fixtures/ssh/key.pub");
+
+    pub fn embedded() -> Result<Self, VerifyError> {
+        Self::from_pem(Self::EMBEDDED_KEY)
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self, VerifyError> {
+        Ok(Verifier { key: PublicKey::from_pem(pem)? })
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, VerifyError> {
+        let pem = std::fs::read(path)
+            .map_err(|e| VerifyError::KeyUnavailable(format!("{}: {}", path.display(), e)))?;
+        Self::from_pem(&pem)
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.key.scheme()
+    }
+
+    /// Checks the signature over the raw data, before anything is made out
+    /// of it.
+    pub fn verify(&self, data: &[u8], sig: &Signature) -> Result<(), VerifyError> {
+        if self.key.verify(data, &sig.0) {
+            Ok(())
+        } else {
+            Err(VerifyError::Mismatch)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Package;
+
+    /// Trusted keys along with their scheme and the signature of the default
+    /// package they made.
+    const KEYS: [(&str, Scheme, &[u8], &str); 4] = [
+        (
+            "key",
+            Scheme::RsaPkcs1Sha256,
+            include_bytes!("../../fixtures/ssh/key.pub"),
+            include_str!("../../fixtures/ssh/key.sig"),
+        ),
+        (
+            "key_rsa_pss",
+            Scheme::RsaPssSha256,
+            include_bytes!("../../fixtures/ssh/key_rsa_pss.pub"),
+            include_str!("../../fixtures/ssh/key_rsa_pss.sig"),
+        ),
+        (
+            "key_ecdsa_p256",
+            Scheme::EcdsaP256Sha256,
+            include_bytes!("../../fixtures/ssh/key_ecdsa_p256.pub"),
+            include_str!("../../fixtures/ssh/key_ecdsa_p256.sig"),
+        ),
+        (
+            "key_ed25519",
+            Scheme::Ed25519,
+            include_bytes!("../../fixtures/ssh/key_ed25519.pub"),
+            include_str!("../../fixtures/ssh/key_ed25519.sig"),
+        ),
+    ];
+
+    #[test]
+    fn verify_samples() {
+        let raw = Package::default().raw;
+        for (name, scheme, pem, sig) in KEYS {
+            let verifier = Verifier::from_pem(pem).unwrap();
+            assert_eq!(verifier.scheme(), scheme, "{}", name);
+            let sig = Signature::from_base64_str(sig.trim()).unwrap();
+            assert!(verifier.verify(&raw, &sig).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reject_modified_body() {
+        let mut raw = Package::default().raw;
+        raw.extend_from_slice(b" ");
+        for (name, _, pem, sig) in KEYS {
+            let verifier = Verifier::from_pem(pem).unwrap();
+            let sig = Signature::from_base64_str(sig.trim()).unwrap();
+            assert!(matches!(verifier.verify(&raw, &sig), Err(VerifyError::Mismatch)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reject_other_key() {
+        let raw = Package::default().raw;
+        let verifier = Verifier::embedded().unwrap();
+        let (_, _, _, sig) = KEYS[1];
+        let sig = Signature::from_base64_str(sig.trim()).unwrap();
+        assert!(matches!(verifier.verify(&raw, &sig), Err(VerifyError::Mismatch)));
+    }
+}
@@ -2,15 +2,16 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use bench::prelude::*;
+use std::net::SocketAddr;
 
 #[tokio::main]
 async fn main() {
-    let (url, _guards) = bench::start_remote_mock();
-    let local_client = LocalClient::new();
-    let remote_client = RemoteClient::new(&url);
-    let app = App::new(remote_client);
-    bench::run(local_client, app).await;
+    let report = match bench::Setup::from_env() {
+        Ok(setup) => bench::run::<LocalClient, App>(&setup).await,
+        Err(e) => bench::ScenarioReport::failed(e),
+    };
+    println!("{}", report.to_json());
+    std::process::exit(report.exit_code());
 }
 
 struct LocalClient {
@@ -22,29 +23,50 @@ struct RemoteClient {
 }
 
 struct App {
-    info: bench::Info,
+    info: bench_core::Info,
     client: RemoteClient,
+    config: bench_core::Config,
 }
 
-type Err = ();
+// The dummy only fails on its state and payloads, whose errors are only read
+// through `Debug` when the harness reports them
+#[allow(dead_code)]
+#[derive(Debug)]
+enum Err {
+    Download(bench_core::DownloadError),
+    State(bench_core::StateError),
+}
+
+impl From<bench_core::DownloadError> for Err {
+    fn from(e: bench_core::DownloadError) -> Self {
+        Err::Download(e)
+    }
+}
+
+impl From<bench_core::StateError> for Err {
+    fn from(e: bench_core::StateError) -> Self {
+        Err::State(e)
+    }
+}
 type Result<T> = std::result::Result<T, Err>;
 
 #[async_trait::async_trait(?Send)]
-impl bench::LocalClientImpl for LocalClient {
+impl bench_core::LocalClientImpl for LocalClient {
     type Err = Err;
 
-    fn new() -> Self {
+    fn new(_: SocketAddr) -> Self {
         LocalClient { requests: 0 }
     }
 
-    async fn fetch_info(&mut self) -> Result<bench::Info> {
-        let info = bench::Info::default();
+    async fn fetch_info(&mut self) -> Result<bench_core::Info> {
+        let info = bench_core::Info::default();
         let res = match self.requests {
             0 | 1 => info,
-            2 => bench::Info { current_version: String::from("0.0.2"), ..info },
-            n => bench::Info {
-                current_version: String::from("0.0.2"),
+            2 => bench_core::Info { current_version: bench_core::Version::new(0, 0, 2), ..info },
+            n => bench_core::Info {
+                current_version: bench_core::Version::new(0, 0, 2),
                 count_invalid_packages: n - 2,
+                ..info
             },
         };
         self.requests += 1;
@@ -53,48 +75,66 @@ impl bench::LocalClientImpl for LocalClient {
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::RemoteClientImpl for RemoteClient {
+impl bench_core::RemoteClientImpl for RemoteClient {
     type Err = Err;
 
-    fn new(_: &str) -> Self {
-        RemoteClient { requests: 0 }
+    fn new(_: &str, _: &bench_core::CaCertificate, _: &bench_core::RequestPolicy) -> Result<Self> {
+        Ok(RemoteClient { requests: 0 })
     }
 
-    async fn fetch_package(&mut self) -> Result<Option<(bench::Package, bench::Signature)>> {
-        let res = match self.requests {
+    fn is_transient(_: &Err) -> bool {
+        false
+    }
+
+    async fn fetch_package(&mut self, _: &bench_core::Validators) -> Result<bench_core::Fetched> {
+        let signature = match self.requests {
             0 => None,
-            1 => Some((
-                bench::Package::parse(&bench::Package::default().raw).unwrap(),
-                bench::Signature::from_base64_str(bench::Signature::VALID_SAMPLE),
-            )),
-            _ => Some((
-                bench::Package::parse(&bench::Package::default().raw).unwrap(),
-                bench::Signature::from_base64_str(bench::Signature::INVALID_SAMPLE),
-            )),
+            1 => Some(bench_core::Signature::VALID_SAMPLE),
+            _ => Some(bench_core::Signature::INVALID_SAMPLE),
         };
         self.requests += 1;
-        Ok(res)
+        Ok(match signature {
+            None => bench_core::Fetched::Nothing,
+            Some(signature) => bench_core::Fetched::Package {
+                body: bench_core::Package::default().raw,
+                signature: bench_core::Signature::from_base64_str(signature).unwrap(),
+                validators: bench_core::Validators::default(),
+            },
+        })
+    }
+
+    async fn download_payload(&mut self, _: &mut bench_core::PayloadWriter) -> Result<()> {
+        Ok(())
+    }
+
+    async fn report(&mut self, _: &bench_core::Report) -> Result<()> {
+        Ok(())
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::AppImpl for App {
+impl bench_core::AppImpl for App {
     type Err = Err;
     type RemoteClient = RemoteClient;
 
-    fn new(client: RemoteClient) -> Self {
-        App { info: bench::Info::default(), client }
+    fn new(client: RemoteClient, config: bench_core::Config) -> Result<Self> {
+        Ok(App { info: config.state.load()?, client, config })
     }
 
-    fn serve(&mut self) -> Result<()> {
+    fn serve(&mut self, addr: SocketAddr, ready: bench_core::Ready) -> Result<()> {
+        ready.notify(addr);
         Ok(())
     }
 
-    async fn map_info<F: FnOnce(&mut bench::Info)>(&mut self, f: F) -> Result<()> {
+    async fn map_info<F: FnOnce(&mut bench_core::Info)>(&mut self, f: F) -> Result<()> {
         Ok(f(&mut self.info))
     }
 
     async fn client(&mut self) -> Result<&mut RemoteClient> {
         Ok(&mut self.client)
     }
+
+    fn config(&self) -> &bench_core::Config {
+        &self.config
+    }
 }
@@ -4,23 +4,24 @@
 
 use derive_more::From;
 use std::{
+    net::SocketAddr,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
 };
 
-use bench::prelude::*;
-
 #[tokio::main]
 async fn main() {
-    let (url, _guards) = bench::start_remote_mock();
-    let local_client = LocalClient::new();
-    let remote_client = RemoteClient::new(&url);
-    let app = App::new(remote_client);
-    bench::run(local_client, app).await;
+    let report = match bench::Setup::from_env() {
+        Ok(setup) => bench::run::<LocalClient, App>(&setup).await,
+        Err(e) => bench::ScenarioReport::failed(e),
+    };
+    println!("{}", report.to_json());
+    std::process::exit(report.exit_code());
 }
 
 struct LocalClient {
     client: reqwest::Client,
+    url: String,
 }
 
 struct RemoteClient {
@@ -29,15 +30,20 @@ struct RemoteClient {
 }
 
 struct App {
-    info: Arc<Mutex<bench::Info>>,
+    info: Arc<Mutex<bench_core::Info>>,
     client: RemoteClient,
+    config: bench_core::Config,
 }
 
 #[derive(Debug, From)]
 enum Err {
     Server(gotham::error::Error),
     Client(reqwest::Error),
-    Parsing(bench::ParsingError),
+    Io(std::io::Error),
+    Signature(bench_core::SignatureError),
+    Download(bench_core::DownloadError),
+    State(bench_core::StateError),
+    Report(bench_core::ReportError),
     MutexPosion,
 }
 
@@ -50,52 +56,105 @@ impl<T> From<std::sync::PoisonError<T>> for Err {
 type Result<T> = std::result::Result<T, Err>;
 
 #[async_trait::async_trait(?Send)]
-impl bench::LocalClientImpl for LocalClient {
+impl bench_core::LocalClientImpl for LocalClient {
     type Err = Err;
 
-    fn new() -> Self {
-        LocalClient { client: reqwest::Client::new() }
+    fn new(server: SocketAddr) -> Self {
+        LocalClient { client: reqwest::Client::new(), url: format!("http://{}", server) }
     }
 
-    async fn fetch_info(&mut self) -> Result<bench::Info> {
-        Ok(self.client.get("http://localhost:8001").send().await?.json().await?)
+    async fn fetch_info(&mut self) -> Result<bench_core::Info> {
+        Ok(self.client.get(&self.url).send().await?.json().await?)
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::RemoteClientImpl for RemoteClient {
+impl bench_core::RemoteClientImpl for RemoteClient {
     type Err = Err;
 
-    fn new(remote: &str) -> Self {
-        RemoteClient { client: reqwest::Client::new(), remote: remote.to_owned() }
+    fn new(
+        remote: &str,
+        ca: &bench_core::CaCertificate,
+        policy: &bench_core::RequestPolicy,
+    ) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&ca.pem)?)
+            .connect_timeout(policy.connect_timeout)
+            .timeout(policy.timeout)
+            .build()?;
+        Ok(RemoteClient { client, remote: remote.to_owned() })
     }
 
-    async fn fetch_package(&mut self) -> Result<Option<(bench::Package, bench::Signature)>> {
-        let response = self.client.get(&self.remote).send().await?;
+    fn is_transient(err: &Err) -> bool {
+        // Statuses are handled by the agent, so any error from the client
+        // itself comes from the connection
+        matches!(err, Err::Client(_))
+    }
 
-        if let reqwest::StatusCode::OK = response.status() {
-            let sign = bench::Signature::from_base64_str(
-                &response.headers().get("Signature").unwrap().to_str().unwrap(),
-            );
-            let pkg = bench::Package::parse(&response.bytes().await?)?;
-            return Ok(Some((pkg, sign)));
+    async fn fetch_package(
+        &mut self,
+        validators: &bench_core::Validators,
+    ) -> Result<bench_core::Fetched> {
+        let mut request = self.client.get(&self.remote);
+        for (name, value) in validators.headers() {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let signature = bench_core::Signature::from_header(
+                    response.headers().get("Signature").map(|value| value.as_bytes()),
+                )?;
+                let validators = bench_core::Validators::from_headers(
+                    response.headers().get("etag").map(|value| value.as_bytes()),
+                    response.headers().get("last-modified").map(|value| value.as_bytes()),
+                );
+                let body = response.bytes().await?.to_vec();
+                Ok(bench_core::Fetched::Package { body, signature, validators })
+            }
+            reqwest::StatusCode::NOT_MODIFIED => Ok(bench_core::Fetched::NotModified),
+            _ => Ok(bench_core::Fetched::Nothing),
+        }
+    }
+
+    async fn download_payload(&mut self, writer: &mut bench_core::PayloadWriter) -> Result<()> {
+        let url = writer.payload().url_from(&self.remote);
+        let mut request = self.client.get(&url);
+        if let Some(range) = writer.range() {
+            request = request.header("range", range);
+        }
+        let mut response = request.send().await?;
+
+        writer.begin(
+            response.status().as_u16(),
+            response.headers().get("content-range").map(|value| value.as_bytes()),
+        )?;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write(&chunk)?;
         }
 
-        Ok(None)
+        Ok(())
+    }
+
+    async fn report(&mut self, report: &bench_core::Report) -> Result<()> {
+        let url = bench_core::Report::url_from(&self.remote);
+        let response = self.client.post(&url).json(report).send().await?;
+        Ok(bench_core::Report::accepted(response.status().as_u16())?)
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::AppImpl for App {
+impl bench_core::AppImpl for App {
     type Err = Err;
     type RemoteClient = RemoteClient;
 
-    fn new(client: RemoteClient) -> Self {
-        let info = Arc::default();
-        App { info, client }
+    fn new(client: RemoteClient, config: bench_core::Config) -> Result<Self> {
+        let info = Arc::new(Mutex::new(config.state.load()?));
+        Ok(App { info, client, config })
     }
 
-    fn serve(&mut self) -> Result<()> {
+    fn serve(&mut self, addr: SocketAddr, ready: bench_core::Ready) -> Result<()> {
         use gotham::{
             handler::{HandlerResult, IntoHandlerError},
             helpers::http::response::create_response,
@@ -108,7 +167,7 @@ impl bench::AppImpl for App {
         use gotham_derive::StateData;
 
         #[derive(Clone, StateData)]
-        struct Info(Arc<Mutex<bench::Info>>);
+        struct Info(Arc<Mutex<bench_core::Info>>);
 
         async fn handle(state: State) -> HandlerResult {
             let res = match serde_json::to_string(state.borrow::<Info>().0.deref()) {
@@ -123,7 +182,7 @@ impl bench::AppImpl for App {
         }
 
         let info = self.info.clone();
-        let srv = gotham::init_server("127.0.0.1:8001", {
+        let router = {
             let middleware = StateMiddleware::new(Info(info.clone()));
             let pipeline = single_middleware(middleware);
             let (chain, pipelines) = single_pipeline(pipeline);
@@ -131,18 +190,30 @@ impl bench::AppImpl for App {
             build_router(chain, pipelines, |route| {
                 route.get("/").to_async(handle);
             })
-        });
+        };
+
+        // Bind before spawning the server so we know when it's ready
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let bound = listener.local_addr()?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let srv = gotham::bind_server(listener, router, futures_util::future::ok);
+        ready.notify(bound);
 
         tokio::spawn(async { srv.await.unwrap() });
 
         Ok(())
     }
 
-    async fn map_info<F: FnOnce(&mut bench::Info)>(&mut self, f: F) -> Result<()> {
+    async fn map_info<F: FnOnce(&mut bench_core::Info)>(&mut self, f: F) -> Result<()> {
         Ok(f(self.info.lock()?.deref_mut()))
     }
 
     async fn client(&mut self) -> Result<&mut RemoteClient> {
         Ok(&mut self.client)
     }
+
+    fn config(&self) -> &bench_core::Config {
+        &self.config
+    }
 }
@@ -5,99 +5,179 @@
 use derive_more::{Display, Error, From};
 use futures_util::lock::Mutex;
 use hyper::{
-    body::Buf,
+    body::{Buf, HttpBody},
     service::{make_service_fn, service_fn},
     Body, Method, Response, Server, StatusCode,
 };
 use std::{
     convert::Infallible,
+    net::SocketAddr,
     ops::{Deref, DerefMut},
     sync::Arc,
+    time::Duration,
 };
 
-use bench::prelude::*;
-
 #[tokio::main]
 async fn main() {
-    let (url, _guards) = bench::start_remote_mock();
-    let local_client = LocalClient::new();
-    let remote_client = RemoteClient::new(&url);
-    let app = App::new(remote_client);
-    bench::run(local_client, app).await;
+    let report = match bench::Setup::from_env() {
+        Ok(setup) => bench::run::<LocalClient, App>(&setup).await,
+        Err(e) => bench::ScenarioReport::failed(e),
+    };
+    println!("{}", report.to_json());
+    std::process::exit(report.exit_code());
 }
 
 struct LocalClient {
     client: hyper::Client<hyper::client::HttpConnector, hyper::body::Body>,
+    url: String,
 }
 
 struct RemoteClient {
-    client: hyper::Client<hyper::client::HttpConnector, hyper::body::Body>,
+    client:
+        hyper::Client<hyper_tls::HttpsConnector<hyper::client::HttpConnector>, hyper::body::Body>,
     remote: String,
+    timeout: Duration,
 }
 
 struct App {
-    info: Arc<Mutex<bench::Info>>,
+    info: Arc<Mutex<bench_core::Info>>,
     client: RemoteClient,
+    config: bench_core::Config,
 }
 
 #[derive(Debug, Display, From, Error)]
 enum Err {
     Hyper(hyper::Error),
-    Parsing(bench::ParsingError),
+    Parsing(bench_core::ParsingError),
     Uri(http::uri::InvalidUri),
+    Http(http::Error),
+    Timeout(tokio::time::Elapsed),
+    Tls(native_tls::Error),
+    Signature(bench_core::SignatureError),
+    Download(bench_core::DownloadError),
+    State(bench_core::StateError),
+    Report(bench_core::ReportError),
 }
 type Result<T> = std::result::Result<T, Err>;
 
 #[async_trait::async_trait(?Send)]
-impl bench::LocalClientImpl for LocalClient {
+impl bench_core::LocalClientImpl for LocalClient {
     type Err = Err;
 
-    fn new() -> Self {
-        LocalClient { client: hyper::Client::new() }
+    fn new(server: SocketAddr) -> Self {
+        LocalClient { client: hyper::Client::new(), url: format!("http://{}", server) }
     }
 
-    async fn fetch_info(&mut self) -> Result<bench::Info> {
-        let res = self.client.get("http://localhost:8001".parse()?).await?;
+    async fn fetch_info(&mut self) -> Result<bench_core::Info> {
+        let res = self.client.get(self.url.parse()?).await?;
         let body = hyper::body::aggregate(res).await?;
         Ok(serde_json::from_slice(body.bytes())?)
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::RemoteClientImpl for RemoteClient {
+impl bench_core::RemoteClientImpl for RemoteClient {
     type Err = Err;
 
-    fn new(remote: &str) -> Self {
-        RemoteClient { client: hyper::Client::new(), remote: remote.to_owned() }
+    fn new(
+        remote: &str,
+        ca: &bench_core::CaCertificate,
+        policy: &bench_core::RequestPolicy,
+    ) -> Result<Self> {
+        let tls = native_tls::TlsConnector::builder()
+            .add_root_certificate(native_tls::Certificate::from_pem(&ca.pem)?)
+            .build()?;
+        let mut http = hyper::client::HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(policy.connect_timeout));
+        let https = hyper_tls::HttpsConnector::from((http, tls.into()));
+        Ok(RemoteClient {
+            client: hyper::Client::builder().build(https),
+            remote: remote.to_owned(),
+            timeout: policy.timeout,
+        })
     }
 
-    async fn fetch_package(&mut self) -> Result<Option<(bench::Package, bench::Signature)>> {
-        let response = self.client.get(self.remote.clone().parse()?).await?;
+    fn is_transient(err: &Err) -> bool {
+        matches!(err, Err::Hyper(_) | Err::Timeout(_))
+    }
 
-        if let StatusCode::OK = response.status() {
-            let sign = bench::Signature::from_base64_str(
-                &response.headers().get("Signature").unwrap().to_str().unwrap(),
-            );
-            let body = hyper::body::aggregate(response).await?;
-            let pkg = bench::Package::parse(&body.bytes())?;
-            return Ok(Some((pkg, sign)));
+    async fn fetch_package(
+        &mut self,
+        validators: &bench_core::Validators,
+    ) -> Result<bench_core::Fetched> {
+        let mut request = hyper::Request::get(self.remote.as_str());
+        for (name, value) in validators.headers() {
+            request = request.header(name, value);
         }
+        let request = request.body(Body::empty())?;
+        // The client only bounds the connection, the rest of the request is
+        // bound here
+        tokio::time::timeout(self.timeout, async {
+            let response = self.client.request(request).await?;
+
+            match response.status() {
+                StatusCode::OK => {
+                    let signature = bench_core::Signature::from_header(
+                        response.headers().get("Signature").map(|value| value.as_bytes()),
+                    )?;
+                    let validators = bench_core::Validators::from_headers(
+                        response.headers().get("etag").map(|value| value.as_bytes()),
+                        response.headers().get("last-modified").map(|value| value.as_bytes()),
+                    );
+                    let body = hyper::body::to_bytes(response).await?.to_vec();
+                    Ok(bench_core::Fetched::Package { body, signature, validators })
+                }
+                StatusCode::NOT_MODIFIED => Ok(bench_core::Fetched::NotModified),
+                _ => Ok(bench_core::Fetched::Nothing),
+            }
+        })
+        .await?
+    }
 
-        Ok(None)
+    async fn download_payload(&mut self, writer: &mut bench_core::PayloadWriter) -> Result<()> {
+        let url = writer.payload().url_from(&self.remote);
+        let mut request = hyper::Request::get(url);
+        if let Some(range) = writer.range() {
+            request = request.header("range", range);
+        }
+        let request = request.body(Body::empty())?;
+        tokio::time::timeout(self.timeout, async {
+            let mut response = self.client.request(request).await?;
+
+            writer.begin(
+                response.status().as_u16(),
+                response.headers().get("content-range").map(|value| value.as_bytes()),
+            )?;
+            while let Some(chunk) = response.body_mut().data().await {
+                writer.write(&chunk?)?;
+            }
+
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn report(&mut self, report: &bench_core::Report) -> Result<()> {
+        let request = hyper::Request::post(bench_core::Report::url_from(&self.remote))
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(report)?))?;
+        let response = tokio::time::timeout(self.timeout, self.client.request(request)).await??;
+        Ok(bench_core::Report::accepted(response.status().as_u16())?)
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::AppImpl for App {
+impl bench_core::AppImpl for App {
     type Err = Err;
     type RemoteClient = RemoteClient;
 
-    fn new(client: RemoteClient) -> Self {
-        let info = Arc::default();
-        App { info, client }
+    fn new(client: RemoteClient, config: bench_core::Config) -> Result<Self> {
+        let info = Arc::new(Mutex::new(config.state.load()?));
+        Ok(App { info, client, config })
     }
 
-    fn serve(&mut self) -> Result<()> {
+    fn serve(&mut self, addr: SocketAddr, ready: bench_core::Ready) -> Result<()> {
         let state = self.info.clone();
         let make_svc = make_service_fn(move |_conn| {
             let state = state.clone();
@@ -121,18 +201,22 @@ impl bench::AppImpl for App {
                 }))
             }
         });
-        let addr = ([127, 0, 0, 1], 8001).into();
-
-        tokio::spawn(async move { Server::bind(&addr).serve(make_svc).await });
+        let server = Server::try_bind(&addr)?.serve(make_svc);
+        ready.notify(server.local_addr());
+        tokio::spawn(server);
 
         Ok(())
     }
 
-    async fn map_info<F: FnOnce(&mut bench::Info)>(&mut self, f: F) -> Result<()> {
+    async fn map_info<F: FnOnce(&mut bench_core::Info)>(&mut self, f: F) -> Result<()> {
         Ok(f(self.info.lock().await.deref_mut()))
     }
 
     async fn client(&mut self) -> Result<&mut RemoteClient> {
         Ok(&mut self.client)
     }
+
+    fn config(&self) -> &bench_core::Config {
+        &self.config
+    }
 }
@@ -0,0 +1,185 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serves the remote mock over HTTPS and runs a contestant against it, handing
+//! the mock's URL and certificate authority through the environment. This
+//! keeps the mock server out of the binaries being measured.
+//!
+//! Usage: `remote_mock [--scenario <scenario.jsonl>] [--key <private key>] <contestant> [args...]`
+//!
+//! The responses are taken from the scenario, `fixtures/scenarios/default.jsonl`
+//! unless another one is given, and its path is handed to the contestant too
+//! so it knows what to expect. Valid packages are signed with `fixtures/ssh/key`
+//! unless another key is given, such as one of the other keys in that
+//! directory, each using a different signature scheme. Once the contestant
+//! exits, the reports it sent are checked against the ones the scenario
+//! expects, failing the run if they don't match.
+
+use bench::scenario::{Answer, Response, Scenario, SignatureKind};
+use bench_core::Report;
+use openssl::pkey::{PKey, Private};
+use std::{ffi::OsString, path::PathBuf, process::Command};
+
+mod server;
+
+const USAGE: &str =
+    "Usage: remote_mock [--scenario <scenario.jsonl>] [--key <private key>] <contestant> [args...]";
+
+/// Key the agent trusts unless told otherwise.
+const DEFAULT_KEY: &str = "fixtures/ssh/key";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+/// Signs the body with the scheme matching the type of the key.
+fn sign(key: &PKey<Private>, body: &[u8]) -> Result<String, openssl::error::ErrorStack> {
+    use openssl::{
+        hash::MessageDigest,
+        pkey::Id,
+        sign::{RsaPssSaltlen, Signer},
+    };
+
+    let mut signer = match key.id() {
+        Id::ED25519 => Signer::new_without_digest(key)?,
+        _ => Signer::new(MessageDigest::sha256(), key)?,
+    };
+    if key.id() == Id::RSA_PSS {
+        // The salt is as long as the digest, which is what every backend
+        // expects
+        signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+    }
+    Ok(openssl::base64::encode_block(&signer.sign_oneshot_to_vec(body)?))
+}
+
+/// Builds the answers of the scenario, in the order they are given.
+fn answers(scenario: &Scenario, key: &PKey<Private>) -> Vec<server::Answer> {
+    scenario
+        .answers()
+        .map(|answer| {
+            let response = match answer {
+                Answer::Fault(fault) => return server::Answer::Fault(fault),
+                Answer::Response(response) => response,
+            };
+            let body = response.body();
+            let mut headers = vec![("content-type", String::from("application/json"))];
+            match response.signature {
+                Some(SignatureKind::Valid) => headers
+                    .push(("signature", sign(key, &body).expect("Unable to sign the response"))),
+                Some(SignatureKind::Invalid) => {
+                    headers.push(("signature", bench_core::Signature::INVALID_SAMPLE.to_owned()))
+                }
+                Some(SignatureKind::NonAscii) => {
+                    headers.push(("signature", String::from("s\u{ed}gn\u{e4}ture")))
+                }
+                Some(SignatureKind::BadBase64) => {
+                    headers.push(("signature", String::from("not*base64!")))
+                }
+                None => {}
+            }
+            if let Some(etag) = &response.etag {
+                headers.push(("etag", etag.clone()));
+            }
+            if let Some(date) = &response.last_modified {
+                headers.push(("last-modified", date.clone()));
+            }
+            server::Answer::Response(server::Response {
+                status: response.status,
+                headers,
+                body,
+                transfer: response.transfer,
+            })
+        })
+        .collect()
+}
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+    let mut scenario = PathBuf::from(bench::scenario::DEFAULT_PATH);
+    let mut key = None;
+    let contestant: OsString = loop {
+        match args.next() {
+            Some(arg) if arg == "--scenario" => {
+                scenario = args.next().map(PathBuf::from).unwrap_or_else(|| usage())
+            }
+            Some(arg) if arg == "--key" => {
+                key = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage()))
+            }
+            Some(arg) => break arg,
+            None => usage(),
+        }
+    };
+
+    let loaded = Scenario::load(&scenario).unwrap_or_else(|e| {
+        eprintln!("Unable to load scenario {}: {}", scenario.display(), e);
+        std::process::exit(2);
+    });
+    let key_path = key.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_KEY));
+    let private_key = std::fs::read(&key_path)
+        .map_err(|e| e.to_string())
+        .and_then(|pem| PKey::private_key_from_pem(&pem).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("Unable to load key {}: {}", key_path.display(), e);
+            std::process::exit(2);
+        });
+    let (addr, server) = server::start(answers(&loaded, &private_key)).unwrap_or_else(|e| {
+        eprintln!("Unable to start the remote mock: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut command = Command::new(&contestant);
+    command
+        .args(args)
+        .env(bench::REMOTE_URL_VAR, format!("https://{}/", addr))
+        .env(bench::REMOTE_CA_VAR, bench::DEFAULT_REMOTE_CA)
+        .env(bench::SCENARIO_VAR, &scenario);
+    // The agent embeds the default key, any other has its public part, stored
+    // next to it, handed over
+    if let Some(key) = key {
+        let mut public = key.into_os_string();
+        public.push(".pub");
+        command.env(bench::TRUSTED_KEY_VAR, public);
+    }
+    let status = command.status().unwrap_or_else(|e| {
+        eprintln!("Unable to run {}: {}", contestant.to_string_lossy(), e);
+        std::process::exit(1);
+    });
+
+    let reports_match = check_reports(&loaded, &server.reports());
+    std::process::exit(status.code().filter(|_| reports_match).unwrap_or(1));
+}
+
+/// Checks each response expecting a report was followed by exactly that one,
+/// printing the ones that weren't.
+fn check_reports(scenario: &Scenario, recorded: &[server::Recorded]) -> bool {
+    let mut matched = true;
+    for (index, answer) in scenario.answers().enumerate() {
+        let expected = match answer {
+            Answer::Response(Response { report: Some(expected), .. }) => expected,
+            _ => continue,
+        };
+        let received = recorded
+            .iter()
+            .filter(|report| report.answer == Some(index))
+            .map(|report| serde_json::from_slice::<Report>(&report.body).map_err(|_| &report.body))
+            .collect::<Vec<_>>();
+        let is_expected = |report: &Report| {
+            report.version == expected.version
+                && report.outcome == expected.outcome
+                && (expected.error.is_none() || report.error == expected.error)
+        };
+        if let [Ok(report)] = received.as_slice() {
+            if is_expected(report) {
+                continue;
+            }
+        }
+        matched = false;
+        eprintln!(
+            "remote_mock: expected report {:?} after answer {}, received {:?}",
+            expected, index, received
+        );
+    }
+    matched
+}
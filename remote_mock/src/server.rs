@@ -0,0 +1,337 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal HTTPS server answering every request with the next answer in its
+//! queue, repeating the last one once the queue is over. Answers are either a
+//! response or a [`Fault`], failing the request instead. Requests under
+//! `/payloads/` are the exception, being served from [`PAYLOADS_DIR`] with
+//! support for `range` requests. Responses carrying an `etag` or a
+//! `last-modified` header are turned into `304 Not Modified` when the request
+//! has matching conditional headers. Reports the agent posts are recorded,
+//! along with the answer they follow, for `remote_mock` to check.
+//!
+//! The certificates it uses are in `fixtures/tls`, and were generated with:
+//!
+//! ```shell
+//! openssl req -x509 -newkey rsa:2048 -nodes -keyout ca.key -out ca.pem -days 36500 \
+//!   -subj "/CN=bench test CA" -addext "basicConstraints=critical,CA:TRUE" \
+//!   -addext "keyUsage=critical,keyCertSign,cRLSign"
+//! openssl req -newkey rsa:2048 -nodes -keyout server.key -out server.csr -subj "/CN=localhost"
+//! openssl x509 -req -in server.csr -CA ca.pem -CAkey ca.key -CAcreateserial -out server.pem \
+//!   -days 36500 -extfile <(printf "subjectAltName=DNS:localhost,IP:127.0.0.1\n\
+//!   basicConstraints=CA:FALSE\nkeyUsage=digitalSignature,keyEncipherment\n\
+//!   extendedKeyUsage=serverAuth")
+//! ```
+
+use bench::scenario::{Fault, Transfer};
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+pub const CERTIFICATE: &str = "fixtures/tls/server.pem";
+pub const PRIVATE_KEY: &str = "fixtures/tls/server.key";
+
+/// Files the manifests can point to as their payload.
+pub const PAYLOADS_DIR: &str = "fixtures/payloads";
+
+/// Limit on the size of a request's head.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Limit on the size of a request's body, which only reports have.
+const MAX_BODY_SIZE: u64 = 16 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+    /// How the payload requests made after this response are answered.
+    pub transfer: Transfer,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| *header == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Answers `304 Not Modified` instead, when the request's conditional
+    /// headers match the response's validators. As in HTTP, `if-none-match`
+    /// takes precedence over `if-modified-since`.
+    fn conditional(self, request: &Request) -> Self {
+        let not_modified = match (request.if_none_match, request.if_modified_since) {
+            (Some(etag), _) => self.header("etag") == Some(etag),
+            (None, Some(date)) => self.header("last-modified") == Some(date),
+            (None, None) => false,
+        };
+        if !not_modified {
+            return self;
+        }
+        let mut response = Response::new(304);
+        response.headers = self
+            .headers
+            .into_iter()
+            .filter(|(name, _)| *name == "etag" || *name == "last-modified")
+            .collect();
+        response
+    }
+
+    fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: Vec::default(),
+            body: Vec::default(),
+            transfer: Transfer::default(),
+        }
+    }
+
+    /// Writes the response, stopping after `limit` bytes of the body, if any,
+    /// as if the connection was lost.
+    fn write_to<W: Write>(&self, mut w: W, limit: Option<u64>) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            206 => "Partial Content",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            404 => "Not Found",
+            416 => "Range Not Satisfiable",
+            _ => "Unknown",
+        };
+        write!(w, "HTTP/1.1 {} {}\r\n", self.status, reason)?;
+        for (name, value) in &self.headers {
+            write!(w, "{}: {}\r\n", name, value)?;
+        }
+        write!(w, "content-length: {}\r\nconnection: close\r\n\r\n", self.body.len())?;
+        let sent = limit.map_or(self.body.len(), |limit| self.body.len().min(limit as usize));
+        w.write_all(&self.body[..sent])?;
+        w.flush()
+    }
+}
+
+/// What a package request is answered with.
+#[derive(Clone, Debug)]
+pub enum Answer {
+    Fault(Fault),
+    Response(Response),
+}
+
+/// A report posted by the agent.
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    /// Index of the answer given to the last package request before the
+    /// report, if there was one.
+    pub answer: Option<usize>,
+    pub body: Vec<u8>,
+}
+
+/// Answers still to be given, and the payload transfer set by the last
+/// response served.
+struct State {
+    queue: VecDeque<Answer>,
+    /// Index of the answer in front of the queue.
+    front: usize,
+    transfer: Transfer,
+    last_answer: Option<usize>,
+    reports: Vec<Recorded>,
+}
+
+/// Handle on the running server, to get what it recorded.
+pub struct Server(Arc<Mutex<State>>);
+
+impl Server {
+    pub fn reports(&self) -> Vec<Recorded> {
+        self.0.lock().unwrap().reports.clone()
+    }
+}
+
+/// Parts of a request the mock answers according to.
+struct Request<'a> {
+    target: &'a str,
+    /// Start of the `range` header, which is only ever open ended.
+    range_start: Option<u64>,
+    content_length: Option<u64>,
+    if_none_match: Option<&'a str>,
+    if_modified_since: Option<&'a str>,
+}
+
+impl<'a> Request<'a> {
+    fn parse(head: &'a [u8]) -> Self {
+        let head = std::str::from_utf8(head).unwrap_or_default();
+        let mut lines = head.lines();
+        let target = lines.next().and_then(|line| line.split_whitespace().nth(1));
+        let headers = lines.filter_map(|line| line.split_once(':'));
+        let header = |name: &str| {
+            headers
+                .clone()
+                .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+        };
+        let range_start = header("range")
+            .and_then(|value| value.strip_prefix("bytes="))
+            .and_then(|range| range.strip_suffix('-'))
+            .and_then(|start| start.parse().ok());
+        Request {
+            target: target.unwrap_or_default(),
+            range_start,
+            content_length: header("content-length").and_then(|len| len.parse().ok()),
+            if_none_match: header("if-none-match"),
+            if_modified_since: header("if-modified-since"),
+        }
+    }
+}
+
+/// Starts giving `answers` in the background, returning the address the
+/// server is bound to.
+pub fn start(answers: Vec<Answer>) -> Result<(SocketAddr, Server), Box<dyn std::error::Error>> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_certificate_chain_file(CERTIFICATE)?;
+    acceptor.set_private_key_file(PRIVATE_KEY, SslFiletype::PEM)?;
+    acceptor.check_private_key()?;
+    let acceptor = Arc::new(acceptor.build());
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let state = Arc::new(Mutex::new(State {
+        queue: answers.into(),
+        front: 0,
+        transfer: Transfer::default(),
+        last_answer: None,
+        reports: Vec::default(),
+    }));
+    let server = Server(state.clone());
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (acceptor, state) = (acceptor.clone(), state.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = handle(&acceptor, &state, stream) {
+                    eprintln!("remote_mock: failed to answer request: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok((addr, server))
+}
+
+fn handle(
+    acceptor: &SslAcceptor,
+    state: &Mutex<State>,
+    stream: TcpStream,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stream = acceptor.accept(stream)?;
+
+    let mut head = Vec::default();
+    let mut buf = [0; 1024];
+    let head_len = loop {
+        if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            // Connection closed without a request, as done by clients
+            // checking whether the server is up
+            return Ok(());
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_HEAD_SIZE {
+            return Err("request head is too large".into());
+        }
+    };
+    let mut body = head.split_off(head_len);
+    let request = Request::parse(&head);
+    let body_len = request.content_length.unwrap_or_default();
+    if body_len > MAX_BODY_SIZE {
+        return Err("request body is too large".into());
+    }
+    while (body.len() as u64) < body_len {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Err("request body is truncated".into());
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+
+    let (response, limit, stall) = {
+        let mut state = state.lock().unwrap();
+        if request.target.strip_prefix('/') == Some(bench_core::Report::PATH) {
+            let answer = state.last_answer;
+            state.reports.push(Recorded { answer, body });
+            (Response::new(200), None, false)
+        } else if let Some(name) = request.target.strip_prefix("/payloads/") {
+            let transfer = state.transfer;
+            let range_start = request.range_start.filter(|_| transfer.ranges);
+            let limit = transfer.stall_after.or(transfer.drop_after);
+            (payload(name, range_start), limit, transfer.stall_after.is_some())
+        } else {
+            state.last_answer = Some(state.front);
+            let answer = if state.queue.len() > 1 {
+                state.front += 1;
+                state.queue.pop_front()
+            } else {
+                state.queue.front().cloned()
+            };
+            match answer.unwrap_or_else(|| Answer::Response(Response::new(404))) {
+                Answer::Response(response) => {
+                    state.transfer = response.transfer;
+                    (response.conditional(&request), None, false)
+                }
+                Answer::Fault(fault) => {
+                    drop(state);
+                    if fault == Fault::Stall {
+                        // Waits for the client to give up, whatever it
+                        // sends in the meantime
+                        let _ = io::copy(&mut stream, &mut io::sink());
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    };
+    response.write_to(&mut stream, limit)?;
+    let dropped = limit.is_some_and(|limit| (limit as usize) < response.body.len());
+    if dropped && stall {
+        // Keeps the connection open until the client gives up on the rest
+        let _ = io::copy(&mut stream, &mut io::sink());
+    } else if !dropped {
+        // The client may have already gone away with the whole response
+        let _ = stream.shutdown();
+    }
+
+    Ok(())
+}
+
+/// Serves the payload from [`PAYLOADS_DIR`], starting at `range_start` when
+/// given.
+fn payload(name: &str, range_start: Option<u64>) -> Response {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Response::new(400);
+    }
+    let body = match std::fs::read(Path::new(PAYLOADS_DIR).join(name)) {
+        Ok(body) => body,
+        Err(_) => return Response::new(404),
+    };
+    let len = body.len() as u64;
+
+    let mut response = Response { body, ..Response::new(200) };
+    response.headers.push(("content-type", String::from("application/octet-stream")));
+    match range_start {
+        None => {}
+        Some(start) if start >= len => {
+            response = Response::new(416);
+            response.headers.push(("content-range", format!("bytes */{}", len)));
+        }
+        Some(start) => {
+            response.status = 206;
+            response.body.drain(..start as usize);
+            response
+                .headers
+                .push(("content-range", format!("bytes {}-{}/{}", start, len - 1, len)));
+        }
+    }
+    response
+}
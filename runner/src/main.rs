@@ -0,0 +1,273 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use bench::report::{Backend, Configuration, Lto, OptLevel, Profile, Symbols};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use structopt::StructOpt;
+
+const WORKSPACE_MEMBERS: &[&str] = &[
+    "dummy",
+    "actix_full",
+    "actix_reqwest",
+    "gotham_reqwest",
+    "hyper_full",
+    "hyper_reqwest",
+    "warp_surf",
+    "tide_surf",
+    "warp_reqwest",
+];
+
+const CODEGEN_UNITS: &[u32] = &[1, 16];
+
+#[derive(StructOpt)]
+enum Opt {
+    /// Build every contestant with each combination of release profile
+    /// settings and crypto backend and collect the size of the crates linked into it with
+    /// `cargo bloat`
+    Collect {
+        /// Directory where one result file is written per configuration
+        #[structopt(long, default_value = "shiny_app/results", parse(from_os_str))]
+        output: PathBuf,
+
+        /// Rebuild configurations that already have a result file
+        #[structopt(long)]
+        force: bool,
+
+        /// Also collect the size of every function linked into the binary
+        #[structopt(long)]
+        symbols: bool,
+
+        /// Only measure the given contestants (defaults to all of them)
+        #[structopt(short, long = "project")]
+        projects: Vec<String>,
+
+        /// Only measure the given crypto backends (defaults to all of them)
+        #[structopt(short, long = "backend")]
+        backends: Vec<Backend>,
+    },
+
+    /// Show the symbols whose size differ the most between two contestants
+    /// built with the same profile
+    Compare {
+        left: String,
+        right: String,
+
+        #[structopt(long, default_value = "openssl")]
+        left_backend: Backend,
+
+        #[structopt(long, default_value = "openssl")]
+        right_backend: Backend,
+
+        /// Directory the results were collected into
+        #[structopt(long, default_value = "shiny_app/results", parse(from_os_str))]
+        output: PathBuf,
+
+        #[structopt(long, default_value = "z")]
+        opt_level: OptLevel,
+
+        #[structopt(long, default_value = "fat")]
+        lto: Lto,
+
+        #[structopt(long, default_value = "1")]
+        codegen_units: u32,
+
+        /// Number of symbols to show
+        #[structopt(short = "n", default_value = "20")]
+        count: usize,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Breakdown {
+    Crates,
+    Symbols,
+}
+
+impl Breakdown {
+    fn path(self, result: &Path) -> PathBuf {
+        match self {
+            Breakdown::Crates => result.to_owned(),
+            Breakdown::Symbols => Configuration::symbols_path(result),
+        }
+    }
+
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            Breakdown::Crates => &["--crates", "-n", "200"],
+            Breakdown::Symbols => &["-n", "0"],
+        }
+    }
+}
+
+/// Runs `cargo bloat` for the configuration, overriding the release profile
+/// through the environment so no cargo configuration file has to be touched.
+/// The project's manifest is given, as cargo applies the features to the
+/// package in the current directory otherwise, which is the harness.
+fn measure(conf: &Configuration, breakdown: Breakdown, output: &Path) -> io::Result<bool> {
+    let manifest = Path::new(&conf.project).join("Cargo.toml");
+    let status = Command::new("cargo")
+        .args(["bloat", "-p", &conf.project, "--release", "--message-format=json"])
+        .arg("--manifest-path")
+        .arg(manifest)
+        .args(breakdown.args())
+        .args(["--no-default-features", "--features", conf.backend.features()])
+        .env("CARGO_PROFILE_RELEASE_OPT_LEVEL", conf.profile.opt_level.as_str())
+        .env("CARGO_PROFILE_RELEASE_LTO", conf.profile.lto.as_str())
+        .env("CARGO_PROFILE_RELEASE_CODEGEN_UNITS", conf.profile.codegen_units.to_string())
+        .stdout(Stdio::from(fs::File::create(output)?))
+        .status()?;
+
+    Ok(status.success() && fs::metadata(output)?.len() > 0)
+}
+
+fn collect(
+    output: &Path,
+    force: bool,
+    symbols: bool,
+    projects: Vec<String>,
+    backends: Vec<Backend>,
+) {
+    let projects = if projects.is_empty() {
+        WORKSPACE_MEMBERS.iter().map(|p| p.to_string()).collect()
+    } else {
+        projects
+    };
+    let backends = if backends.is_empty() { Backend::ALL.to_vec() } else { backends };
+
+    if let Err(e) = fs::create_dir_all(output) {
+        eprintln!("Unable to create {}: {}", output.display(), e);
+        std::process::exit(1);
+    }
+
+    let breakdowns: &[Breakdown] =
+        if symbols { &[Breakdown::Crates, Breakdown::Symbols] } else { &[Breakdown::Crates] };
+
+    let mut configurations = Vec::default();
+    for &opt_level in OptLevel::ALL.iter() {
+        for project in &projects {
+            for &backend in &backends {
+                for &lto in Lto::ALL.iter() {
+                    for &codegen_units in CODEGEN_UNITS {
+                        let profile = Profile { opt_level, lto, codegen_units };
+                        let project = project.clone();
+                        configurations.push(Configuration { project, backend, profile });
+                    }
+                }
+            }
+        }
+    }
+
+    let total = configurations.len();
+    let mut failures = Vec::default();
+    for (n, conf) in configurations.iter().enumerate() {
+        let result = output.join(conf.file_name());
+        for &breakdown in breakdowns {
+            let target = breakdown.path(&result);
+            let name = target.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if target.exists() && !force {
+                eprintln!("[{}/{}] {}: skipped, result already exists", n + 1, total, name);
+                continue;
+            }
+
+            eprintln!("[{}/{}] {}: measuring", n + 1, total, name);
+            // Results are written to a hidden file first and only moved into
+            // place once complete, so interrupting the runner never leaves a
+            // truncated result behind and the next run resumes from there.
+            let partial = output.join(format!(".{}.partial", name));
+            match measure(conf, breakdown, &partial) {
+                Ok(true) => {
+                    // The sidecar only follows a result that made it into
+                    // place, and the result is taken out again without it, so
+                    // the next run measures it once more.
+                    if let Err(e) = fs::rename(&partial, &target) {
+                        let _ = fs::remove_file(&partial);
+                        failures.push((name, e.to_string()));
+                    } else if let Err(e) = conf.write_sidecar(&result) {
+                        let _ = fs::remove_file(&target);
+                        failures.push((name, e.to_string()));
+                    }
+                }
+                Ok(false) => {
+                    let _ = fs::remove_file(&partial);
+                    failures.push((name, String::from("cargo bloat failed")));
+                }
+                Err(e) => {
+                    let _ = fs::remove_file(&partial);
+                    failures.push((name, e.to_string()));
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        eprintln!("\n{} of {} results failed:", failures.len(), total * breakdowns.len());
+        for (name, reason) in failures {
+            eprintln!("  {}: {}", name, reason);
+        }
+        std::process::exit(1);
+    }
+}
+
+fn compare(
+    output: &Path,
+    left: (String, Backend),
+    right: (String, Backend),
+    profile: Profile,
+    count: usize,
+) {
+    let load = |(project, backend)| {
+        let result = output.join(Configuration { project, backend, profile }.file_name());
+        Symbols::load(&result).unwrap_or_else(|e| {
+            eprintln!("Unable to load the symbols of {}: {}", result.display(), e);
+            eprintln!("They can be collected with `bench-runner collect --symbols`");
+            std::process::exit(1);
+        })
+    };
+    let (left, right) = (load(left), load(right));
+
+    let label = |symbols: &Symbols| match symbols.configuration.backend {
+        Backend::Openssl => symbols.configuration.project.clone(),
+        backend => format!("{}+{}", symbols.configuration.project, backend.as_str()),
+    };
+    println!("{:>10} {:>10} {:>10}  {:<20} Symbol", "Delta", label(&left), label(&right), "Crate");
+    for delta in left.diff(&right, count) {
+        println!(
+            "{:>+10} {:>10} {:>10}  {:<20} {}",
+            delta.delta(),
+            delta.left,
+            delta.right,
+            delta.krate.as_deref().unwrap_or("[Unknown]"),
+            delta.name
+        );
+    }
+}
+
+fn main() {
+    match Opt::from_args() {
+        Opt::Collect { output, force, symbols, projects, backends } => {
+            collect(&output, force, symbols, projects, backends)
+        }
+        Opt::Compare {
+            left,
+            right,
+            left_backend,
+            right_backend,
+            output,
+            opt_level,
+            lto,
+            codegen_units,
+            count,
+        } => compare(
+            &output,
+            (left, left_backend),
+            (right, right_backend),
+            Profile { opt_level, lto, codegen_units },
+            count,
+        ),
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A clock under the harness' control, so the agent's waits take no time and
+//! the times it reports are the same on every run.
+
+use bench_core::agent::Clock;
+use std::{
+    cell::Cell,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Clock that only moves when slept on. Clones share the same time, so it
+/// keeps going when the app is restarted.
+#[derive(Clone, Debug)]
+pub struct FakeClock {
+    now: Rc<Cell<SystemTime>>,
+}
+
+impl Default for FakeClock {
+    /// Starts at the Unix epoch, so times reported are seconds since the
+    /// scenario started.
+    fn default() -> Self {
+        FakeClock { now: Rc::new(Cell::new(UNIX_EPOCH)) }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Clock for FakeClock {
+    fn now(&self) -> SystemTime {
+        self.now.get()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
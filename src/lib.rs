@@ -2,189 +2,331 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use serde::{Deserialize, Serialize};
+//! Benchmark scaffolding used to drive a contestant through the scenario. The
+//! agent code itself lives in `bench-core`, so size reports can tell both
+//! apart.
 
-pub use serde_json::Error as ParsingError;
+use bench_core::{
+    agent::{Agent, Polling},
+    AppImpl, CaCertificate, Config, Identity, Info, LocalClientImpl, RemoteClientImpl,
+    RequestPolicy, StateStore, Verifier,
+};
+use clock::FakeClock;
+use futures_channel::oneshot;
+use scenario::Scenario;
+use serde::Serialize;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-pub mod prelude {
-    pub use super::{AppImpl, LocalClientImpl, RemoteClientImpl};
-}
+pub mod clock;
+pub mod report;
+pub mod scenario;
 
-#[async_trait::async_trait(?Send)]
-pub trait LocalClientImpl: Sized {
-    type Err: std::fmt::Debug;
-    fn new() -> Self;
-    async fn fetch_info(&mut self) -> Result<Info, Self::Err>;
-}
+/// Environment variable used by `remote_mock` to tell the contestant where the
+/// remote server is.
+pub const REMOTE_URL_VAR: &str = "BENCH_REMOTE_URL";
 
-#[async_trait::async_trait(?Send)]
-pub trait RemoteClientImpl: Sized {
-    type Err;
+/// Environment variable used by `remote_mock` to tell the contestant which
+/// certificate authority its TLS certificate is issued by.
+pub const REMOTE_CA_VAR: &str = "BENCH_REMOTE_CA";
 
-    fn new(url: &str) -> Self;
-    async fn fetch_package(&mut self) -> Result<Option<(Package, Signature)>, Self::Err>;
-}
+/// Certificate authority used when none is given.
+pub const DEFAULT_REMOTE_CA: &str = "fixtures/tls/ca.pem";
 
-#[async_trait::async_trait(?Send)]
-pub trait AppImpl: Sized {
-    type RemoteClient: RemoteClientImpl;
-    type Err: From<<Self::RemoteClient as RemoteClientImpl>::Err> + std::fmt::Debug;
+/// Environment variable used by `remote_mock` to tell the contestant which
+/// scenario it is serving.
+pub const SCENARIO_VAR: &str = "BENCH_SCENARIO";
 
-    fn new(client: Self::RemoteClient) -> Self;
-    fn serve(&mut self) -> Result<(), Self::Err>;
+/// Environment variable that points to the key packages are signed with,
+/// instead of the one embedded in the agent.
+pub const TRUSTED_KEY_VAR: &str = "BENCH_TRUSTED_KEY";
 
-    async fn map_info<F: FnOnce(&mut Info)>(&mut self, f: F) -> Result<(), Self::Err>;
-    async fn client(&mut self) -> Result<&mut Self::RemoteClient, Self::Err>;
+/// Environment variable that points to the JSON description of the device's
+/// `Identity`.
+pub const IDENTITY_VAR: &str = "BENCH_IDENTITY";
 
-    async fn process(&mut self) -> Result<(), Self::Err> {
-        match self.client().await?.fetch_package().await? {
-            None => {}
-            Some((pkg, sig)) => {
-                if sig.validate(&pkg) {
-                    self.map_info(move |info| info.current_version = pkg.version).await?;
-                    return Ok(());
-                }
-                self.map_info(move |info| info.count_invalid_packages += 1).await?;
-            }
+/// Identity used when none is given, matching the product of the fixture
+/// packages.
+pub const DEFAULT_IDENTITY: &str = "fixtures/identity.json";
+
+/// Environment variable that overrides the directory payloads are downloaded
+/// to, which is a new one in the system's temporary directory by default.
+pub const DOWNLOAD_DIR_VAR: &str = "BENCH_DOWNLOAD_DIR";
+
+/// Environment variable that overrides the file the agent's state is saved
+/// to, which is in a new directory in the system's temporary one by default.
+pub const STATE_PATH_VAR: &str = "BENCH_STATE_PATH";
+
+/// Environment variable that overrides where the local server binds to.
+pub const BIND_ADDR_VAR: &str = "BENCH_BIND_ADDR";
+
+/// How the agent polls during the scenario. There is no jitter, so the
+/// scenarios can tell when the next poll is due.
+const POLLING: Polling = Polling {
+    interval: Duration::from_secs(60),
+    jitter: Duration::from_secs(0),
+    backoff: Duration::from_secs(10),
+    max_backoff: Duration::from_secs(40),
+};
+
+/// Bounds on the requests to the mock, short enough for the scenarios where
+/// it stalls not to take long. Retries wait on the fake clock, so their delay
+/// shows in `next_poll` without taking any time.
+const REQUEST: RequestPolicy = RequestPolicy {
+    connect_timeout: Duration::from_secs(1),
+    timeout: Duration::from_secs(1),
+    retries: 2,
+    retry_delay: Duration::from_secs(5),
+};
+
+/// How long the local server is given to bind before the scenario fails.
+const SERVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parameters handed to the contestant by the environment it runs in.
+pub struct Setup {
+    pub remote_url: String,
+    pub remote_ca: CaCertificate,
+    /// Address the local server binds to, on an ephemeral port by default so
+    /// contestants can run side by side.
+    pub bind_addr: SocketAddr,
+    pub scenario: Scenario,
+    pub config: Config,
+    /// Directory holding the downloads and the state, unless they are placed
+    /// elsewhere, which is removed once the scenario is over.
+    pub work_dir: PathBuf,
+}
+
+impl Setup {
+    /// Fails when a variable or one of the files it points to is invalid, so
+    /// the contestant reports it through a failed `ScenarioReport`.
+    pub fn from_env() -> Result<Self, String> {
+        let remote_url = std::env::var(REMOTE_URL_VAR).map_err(|_| {
+            format!("{} is not set, contestants must be run through remote_mock", REMOTE_URL_VAR)
+        })?;
+        let ca_path = PathBuf::from(
+            std::env::var_os(REMOTE_CA_VAR).unwrap_or_else(|| DEFAULT_REMOTE_CA.into()),
+        );
+        let remote_ca = CaCertificate {
+            pem: std::fs::read(&ca_path)
+                .map_err(|e| format!("Unable to read certificate {}: {}", ca_path.display(), e))?,
+            path: ca_path,
+        };
+        let bind_addr = std::env::var(BIND_ADDR_VAR)
+            .unwrap_or_else(|_| String::from("127.0.0.1:0"))
+            .parse()
+            .map_err(|e| format!("{} is not a valid address: {}", BIND_ADDR_VAR, e))?;
+        let scenario =
+            std::env::var(SCENARIO_VAR).unwrap_or_else(|_| String::from(scenario::DEFAULT_PATH));
+        let scenario = Scenario::load(Path::new(&scenario))
+            .map_err(|e| format!("Unable to load scenario {}: {}", scenario, e))?;
+
+        let verifier = match std::env::var_os(TRUSTED_KEY_VAR) {
+            Some(path) => Verifier::from_path(Path::new(&path)),
+            None => Verifier::embedded(),
         }
+        .map_err(|e| format!("Unable to load the trusted key: {}", e))?;
+
+        let identity =
+            std::env::var(IDENTITY_VAR).unwrap_or_else(|_| String::from(DEFAULT_IDENTITY));
+        let identity = std::fs::read(&identity)
+            .map_err(|e| e.to_string())
+            .and_then(|content| Identity::parse(&content).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Unable to load identity {}: {}", identity, e))?;
+
+        let work_dir = std::env::temp_dir().join(format!("bench-{}", std::process::id()));
+        let download_dir = std::env::var_os(DOWNLOAD_DIR_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| work_dir.join("downloads"));
+        let state = std::env::var_os(STATE_PATH_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| work_dir.join("state.json"));
 
-        Ok(())
+        Ok(Setup {
+            remote_url,
+            remote_ca,
+            bind_addr,
+            scenario,
+            config: Config {
+                verifier,
+                identity,
+                download_dir,
+                state: StateStore::new(state),
+                polling: POLLING,
+                request: REQUEST,
+            },
+            work_dir,
+        })
     }
 }
 
-pub async fn run<C: LocalClientImpl, A: AppImpl>(mut client: C, mut app: A) {
-    app.serve().unwrap(); // Start serving the app for the local client
-
-    // Give time for the server to actually start
-    std::thread::sleep(std::time::Duration::from_secs(1));
-
-    let info = client.fetch_info().await.unwrap();
-    assert_eq!(info, Info::default(), "Info should be default as nothing has run so far");
-
-    app.process().await.unwrap();
-    let info = client.fetch_info().await.unwrap();
-    assert_eq!(info, Info::default(), "Info should still be default as update will not apply yet");
-
-    app.process().await.unwrap();
-    let info = client.fetch_info().await.unwrap();
-    assert_eq!(
-        info,
-        Info { current_version: String::from("0.0.2"), count_invalid_packages: 0 },
-        "Info should show the updated current_version"
-    );
-
-    app.process().await.unwrap();
-    let info = client.fetch_info().await.unwrap();
-    assert_eq!(
-        info,
-        Info { current_version: String::from("0.0.2"), count_invalid_packages: 1 },
-        "Info should show the updated current_version with the updated count of invalid packages"
-    );
-
-    app.process().await.unwrap();
-    let info = client.fetch_info().await.unwrap();
-    assert_eq!(
-        info,
-        Info { current_version: String::from("0.0.2"), count_invalid_packages: 2 },
-        "Info should show increase in the count of invalid packages"
-    );
+/// Outcome of one step of the scenario.
+#[derive(Debug, Serialize)]
+pub struct StepReport {
+    pub description: String,
+    pub expected: Info,
+    pub actual: Option<Info>,
+    pub elapsed_ms: u64,
+    pub error: Option<String>,
 }
 
-pub fn start_remote_mock() -> (String, Vec<mockito::Mock>) {
-    let mut guards = Vec::default();
-    let body = Package::default().raw;
-    guards.push(mockito::mock("GET", "/").with_status(404).create());
-    guards.push(
-        mockito::mock("GET", "/")
-            .with_status(200)
-            .with_header("signature", Signature::VALID_SAMPLE)
-            .with_body(&body)
-            .create(),
-    );
-    guards.push(
-        mockito::mock("GET", "/")
-            .with_status(200)
-            .with_header("signature", Signature::INVALID_SAMPLE)
-            .with_body(&body)
-            .expect_at_least(2)
-            .create(),
-    );
-
-    (mockito::server_url(), guards)
+impl StepReport {
+    /// The time of the next poll and the validators are only checked when
+    /// the step expects them.
+    pub fn passed(&self) -> bool {
+        let actual = self.actual.as_ref().map(|actual| Info {
+            next_poll: self.expected.next_poll.and(actual.next_poll),
+            validators: self.expected.validators.as_ref().and(actual.validators.clone()),
+            ..actual.clone()
+        });
+        self.error.is_none() && actual.as_ref() == Some(&self.expected)
+    }
 }
 
-#[derive(Debug)]
-pub struct Signature(pub Vec<u8>);
+/// Outcome of running a contestant through the whole scenario.
+#[derive(Debug, Default, Serialize)]
+pub struct ScenarioReport {
+    /// Failure that prevented the steps from running at all.
+    pub error: Option<String>,
+    pub steps: Vec<StepReport>,
+}
 
-impl Signature {
-    pub const INVALID_SAMPLE: &'static str = r#"Hx6kv5dndxA/3qi9QAgXlaiyCrhKZLE7TLXVHVIU9XNq0qIyuRWCDaBDSXCbFKTgd26gBY6q30FHpxrDuf09UPnznluxv/0LbGbwyyskj4c5CZwQIGCcj+5a+ypV68G7hzFsaY3l7COvtGfQPnFT3B7JovqoLTpNgh/VtI0PHDo="#;
-    /// Get a valid signature. Static signature generated with:
-    /// ```shell
-    /// echo -n '{"product":"fooobarrr","version":"0.0.2"}' | \
-    ///   openssl dgst -sha256 -sign fixtures/ssh/key | base64
-    /// ```
-    pub const VALID_SAMPLE: &'static str = r#"xcPhKCRaL3YheiVvJOhypjFKW7e8sJzyIve2k+Higp+BtB5ED31rW3wl/noDqvIA7YVyWVnEE/nzRfRrjNOE1ylbxwUuOsjRamCr2y6C8q7rBshA6msRmwsVAmIKHcjGWhL/p1bF9WjS7vNbItx0ujHuDlqgTwutvM9XN702IjE="#;
+impl ScenarioReport {
+    /// Report of a scenario that couldn't be started at all.
+    pub fn failed(error: String) -> Self {
+        ScenarioReport { error: Some(error), ..ScenarioReport::default() }
+    }
 
-    pub fn from_base64_str(content: &str) -> Self {
-        Signature(openssl::base64::decode_block(content).unwrap().to_vec())
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.steps.iter().all(StepReport::passed)
     }
 
-    pub fn validate(&self, pkg: &Package) -> bool {
-        use openssl::{hash::MessageDigest, pkey::PKey, rsa::Rsa, sign::Verifier};
-        let fun = move || {
-            let content = &std::fs::read("fixtures/ssh/key.pub").unwrap();
-            let key = Rsa::public_key_from_pem(content)?;
-            let key = PKey::from_rsa(key)?;
-            let mut ver = Verifier::new(MessageDigest::sha256(), &key)?;
-            Result::<bool, openssl::error::ErrorStack>::Ok(ver.verify_oneshot(&self.0, &pkg.raw)?)
-        };
-        fun().unwrap_or_default()
+    pub fn exit_code(&self) -> i32 {
+        if self.passed() {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Report is always serializable")
     }
 }
 
-#[derive(Debug)]
-pub struct Package {
-    pub product_uid: String,
-    pub version: String,
-    pub raw: Vec<u8>,
+/// Creates the app and runs it through the steps of the scenario, checking the
+/// `Info` served by the local server after each of them.
+pub async fn run<C: LocalClientImpl, A: AppImpl>(setup: &Setup) -> ScenarioReport {
+    let report = run_steps::<C, A>(setup).await;
+    // It may not even exist, if everything was placed elsewhere
+    let _ = std::fs::remove_dir_all(&setup.work_dir);
+    report
 }
 
-impl Default for Package {
-    fn default() -> Self {
-        Package {
-            product_uid: String::from("fooobarrr"),
-            version: String::from("0.0.2"),
-            raw: br#"{"product":"fooobarrr","version":"0.0.2"}"#.to_vec(),
+async fn run_steps<C: LocalClientImpl, A: AppImpl>(setup: &Setup) -> ScenarioReport {
+    let mut report = ScenarioReport::default();
+
+    let clock = FakeClock::default();
+    let (mut agent, mut client) = match boot::<C, A>(setup, setup.bind_addr, &clock).await {
+        Ok(started) => started,
+        Err(e) => {
+            report.error = Some(e);
+            return report;
         }
-    }
-}
+    };
 
-impl Package {
-    pub fn parse(content: &[u8]) -> Result<Self, ParsingError> {
-        #[derive(Deserialize)]
-        struct PackageAux {
-            #[serde(rename = "product")]
-            product_uid: String,
-            version: String,
+    for step in &setup.scenario.steps {
+        let start = Instant::now();
+        let mut error = None;
+        if step.restart {
+            // The previous local server can't be stopped through `AppImpl`, so
+            // it is left behind and the new one takes another port
+            let addr = SocketAddr::new(setup.bind_addr.ip(), 0);
+            match boot::<C, A>(setup, addr, &clock).await {
+                Ok((restarted, reconnected)) => {
+                    agent = restarted;
+                    client = reconnected;
+                }
+                Err(e) => {
+                    report.error = Some(format!("Restart failed: {}", e));
+                    return report;
+                }
+            }
+        }
+        let downloads = &setup.config.download_dir;
+        if step.block_downloads {
+            // A file can't be written into, even by root, which is not true
+            // of a directory without permissions
+            let _ = std::fs::remove_dir_all(downloads);
+            if let Err(e) = std::fs::write(downloads, b"") {
+                error = Some(format!("Blocking downloads failed: {}", e));
+            }
         }
+        if step.response.is_some() || !step.faults.is_empty() {
+            match agent.tick().await {
+                Err(e) if !step.block_downloads => {
+                    error = Some(format!("Processing failed: {:?}", e));
+                }
+                Ok(()) if step.block_downloads => {
+                    error.get_or_insert_with(|| {
+                        String::from("Processing succeeded with downloads blocked")
+                    });
+                }
+                _ => {}
+            }
+        }
+        if step.block_downloads {
+            let _ = std::fs::remove_file(downloads);
+        }
+        let actual = match client.fetch_info().await {
+            Ok(info) => Some(info),
+            Err(e) => {
+                error.get_or_insert_with(|| format!("Fetching info failed: {:?}", e));
+                None
+            }
+        };
 
-        let update_package = serde_json::from_slice::<PackageAux>(content)?;
-        Ok(Package {
-            product_uid: update_package.product_uid,
-            version: update_package.version,
-            raw: content.to_vec(),
-        })
+        report.steps.push(StepReport {
+            description: step.description.clone(),
+            expected: step.expect.clone(),
+            actual,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+            error,
+        });
     }
+
+    report
 }
 
-#[derive(Deserialize, Debug, Serialize, PartialEq)]
-pub struct Info {
-    pub current_version: String,
-    pub count_invalid_packages: u32,
+/// Creates the app, as done when the agent boots, and starts serving it for
+/// the local client.
+async fn boot<C: LocalClientImpl, A: AppImpl>(
+    setup: &Setup,
+    addr: SocketAddr,
+    clock: &FakeClock,
+) -> Result<(Agent<A, FakeClock>, C), String> {
+    let remote = A::RemoteClient::new(&setup.remote_url, &setup.remote_ca, &setup.config.request)
+        .map_err(|e| format!("Unable to create the remote client: {:?}", e))?;
+    let mut app = A::new(remote, setup.config.clone())
+        .map_err(|e| format!("Unable to create the app: {:?}", e))?;
+
+    let (ready, bound) = bench_core::Ready::new();
+    app.serve(addr, ready).map_err(|e| format!("Unable to serve: {:?}", e))?;
+    let client = C::new(wait_ready(bound).await?);
+
+    Ok((Agent::new(app, clock.clone()), client))
 }
 
-impl Default for Info {
-    fn default() -> Self {
-        Info { current_version: String::from("0.0.1"), count_invalid_packages: 0 }
+async fn wait_ready(bound: oneshot::Receiver<SocketAddr>) -> Result<SocketAddr, String> {
+    use futures_util::future::{select, Either};
+
+    match select(bound, futures_timer::Delay::new(SERVE_TIMEOUT)).await {
+        Either::Left((Ok(addr), _)) => Ok(addr),
+        Either::Left((Err(oneshot::Canceled), _)) => {
+            Err(String::from("Local server stopped before being ready"))
+        }
+        Either::Right(_) => Err(format!("Local server was not ready after {:?}", SERVE_TIMEOUT)),
     }
 }
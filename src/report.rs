@@ -0,0 +1,577 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Typed access to the results collected by `bench-runner`.
+//!
+//! Each configuration produces a `cargo bloat --crates --message-format=json`
+//! file named after the configuration, plus a `.meta.json` sidecar with the
+//! same configuration in a structured form. When symbols are collected too,
+//! the per-function output is stored next to it with a `.symbols.json`
+//! extension.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const SIDECAR_EXTENSION: &str = "meta.json";
+const SYMBOLS_EXTENSION: &str = "symbols.json";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parsing(serde_json::Error),
+    UnknownConfiguration(PathBuf),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parsing(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Parsing(e) => write!(f, "invalid result: {}", e),
+            Error::UnknownConfiguration(p) => {
+                write!(f, "unable to find the configuration of {}", p.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum OptLevel {
+    #[serde(rename = "0")]
+    O0,
+    #[serde(rename = "1")]
+    O1,
+    #[serde(rename = "2")]
+    O2,
+    #[serde(rename = "3")]
+    O3,
+    #[serde(rename = "s")]
+    S,
+    #[serde(rename = "z")]
+    Z,
+}
+
+impl OptLevel {
+    pub const ALL: [OptLevel; 6] =
+        [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3, OptLevel::S, OptLevel::Z];
+
+    /// Value as accepted by cargo's `opt-level` profile setting.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "0",
+            OptLevel::O1 => "1",
+            OptLevel::O2 => "2",
+            OptLevel::O3 => "3",
+            OptLevel::S => "s",
+            OptLevel::Z => "z",
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OptLevel::ALL
+            .iter()
+            .copied()
+            .find(|o| o.as_str() == s)
+            .ok_or_else(|| format!("invalid opt-level: {}", s))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lto {
+    Thin,
+    Fat,
+}
+
+impl Lto {
+    pub const ALL: [Lto; 2] = [Lto::Thin, Lto::Fat];
+
+    /// Value as accepted by cargo's `lto` profile setting.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Lto::Thin => "thin",
+            Lto::Fat => "fat",
+        }
+    }
+}
+
+impl FromStr for Lto {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lto::ALL
+            .iter()
+            .copied()
+            .find(|l| l.as_str() == s)
+            .ok_or_else(|| format!("invalid lto: {}", s))
+    }
+}
+
+/// Implementation of the cryptographic primitives used by the agent, picked
+/// through `bench-core`'s features.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Openssl,
+    Rust,
+}
+
+impl Default for Backend {
+    /// Results collected before the backend could be picked all used openssl.
+    fn default() -> Self {
+        Backend::Openssl
+    }
+}
+
+impl Backend {
+    pub const ALL: [Backend; 2] = [Backend::Openssl, Backend::Rust];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Openssl => "openssl",
+            Backend::Rust => "rust",
+        }
+    }
+
+    /// Cargo features enabling the backend on a contestant.
+    pub fn features(self) -> &'static str {
+        match self {
+            Backend::Openssl => "crypto-openssl",
+            Backend::Rust => "crypto-rust",
+        }
+    }
+
+    /// Suffix added to the project in result file names. The default backend
+    /// has none, so its results keep the names they always had.
+    fn suffix(self) -> &'static str {
+        match self {
+            Backend::Openssl => "",
+            Backend::Rust => "_crypto_rust",
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Backend::ALL
+            .iter()
+            .copied()
+            .find(|b| b.as_str() == s)
+            .ok_or_else(|| format!("invalid backend: {}", s))
+    }
+}
+
+/// Release profile settings a contestant was built with.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Profile {
+    pub opt_level: OptLevel,
+    pub lto: Lto,
+    pub codegen_units: u32,
+}
+
+/// A contestant built with a given profile and crypto backend, which is what
+/// identifies a result file.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Configuration {
+    pub project: String,
+    #[serde(default)]
+    pub backend: Backend,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+impl Configuration {
+    /// Name of the result file, kept compatible with the shiny app, which
+    /// shows each backend as a project of its own.
+    pub fn file_name(&self) -> String {
+        format!(
+            "{}{}_opt_{}_lto_{}_cg_{}",
+            self.project,
+            self.backend.suffix(),
+            self.profile.opt_level.as_str(),
+            self.profile.lto.as_str(),
+            self.profile.codegen_units
+        )
+    }
+
+    /// Decodes a configuration from a result file name, used for results
+    /// collected before sidecars were written.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let (project, rest) = name.split_once("_opt_")?;
+        let (opt_level, rest) = rest.split_once("_lto_")?;
+        let (lto, codegen_units) = rest.split_once("_cg_")?;
+        let (project, backend) = match project.strip_suffix(Backend::Rust.suffix()) {
+            Some(project) => (project, Backend::Rust),
+            None => (project, Backend::Openssl),
+        };
+
+        Some(Configuration {
+            project: project.to_owned(),
+            backend,
+            profile: Profile {
+                opt_level: opt_level.parse().ok()?,
+                lto: lto.parse().ok()?,
+                codegen_units: codegen_units.parse().ok()?,
+            },
+        })
+    }
+
+    /// Path of the metadata sidecar for a result file.
+    pub fn sidecar_path(result: &Path) -> PathBuf {
+        with_extension(result, SIDECAR_EXTENSION)
+    }
+
+    /// Path of the per-function breakdown for a result file.
+    pub fn symbols_path(result: &Path) -> PathBuf {
+        with_extension(result, SYMBOLS_EXTENSION)
+    }
+
+    pub fn write_sidecar(&self, result: &Path) -> Result<(), Error> {
+        fs::write(Self::sidecar_path(result), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Reads the configuration of a result file from its sidecar, falling
+    /// back to decoding the file name.
+    pub fn for_result(result: &Path) -> Result<Self, Error> {
+        let sidecar = Self::sidecar_path(result);
+        if sidecar.exists() {
+            return Ok(serde_json::from_slice(&fs::read(sidecar)?)?);
+        }
+
+        result
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(Self::from_file_name)
+            .ok_or_else(|| Error::UnknownConfiguration(result.to_owned()))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CrateSize {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Crate sizes of a contestant built with a given configuration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Measurement {
+    #[serde(flatten)]
+    pub configuration: Configuration,
+    pub file_size: u64,
+    pub text_section_size: u64,
+    pub crates: Vec<CrateSize>,
+}
+
+impl Measurement {
+    pub fn load(result: &Path) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct BloatOutput {
+            file_size: u64,
+            text_section_size: u64,
+            crates: Vec<CrateSize>,
+        }
+
+        let configuration = Configuration::for_result(result)?;
+        let output = serde_json::from_slice::<BloatOutput>(&fs::read(result)?)?;
+        Ok(Measurement {
+            configuration,
+            file_size: output.file_size,
+            text_section_size: output.text_section_size,
+            crates: output.crates,
+        })
+    }
+
+    /// Loads every result stored in a directory, ignoring sidecars, symbol
+    /// breakdowns and hidden files.
+    pub fn load_dir(dir: &Path) -> Result<Vec<Self>, Error> {
+        let mut measurements = Vec::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if name.contains('.') || !path.is_file() {
+                continue;
+            }
+            measurements.push(Self::load(&path)?);
+        }
+
+        Ok(measurements)
+    }
+
+    /// Sum of the sizes of all crates, which is what the shiny app shows as
+    /// the project's total.
+    pub fn total(&self) -> u64 {
+        self.crates.iter().map(|c| c.size).sum()
+    }
+
+    pub fn crate_size(&self, name: &str) -> Option<u64> {
+        self.crates.iter().find(|c| c.name == name).map(|c| c.size)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SymbolSize {
+    /// Crate the symbol belongs to, when `cargo bloat` is able to tell.
+    #[serde(rename = "crate")]
+    pub krate: Option<String>,
+    pub name: String,
+    pub size: u64,
+}
+
+/// Per-function sizes of a contestant built with a given configuration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Symbols {
+    #[serde(flatten)]
+    pub configuration: Configuration,
+    pub file_size: u64,
+    pub text_section_size: u64,
+    pub functions: Vec<SymbolSize>,
+}
+
+/// Size of the same symbol in two different builds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SymbolDelta {
+    pub krate: Option<String>,
+    pub name: String,
+    pub left: u64,
+    pub right: u64,
+}
+
+impl SymbolDelta {
+    pub fn delta(&self) -> i64 {
+        self.right as i64 - self.left as i64
+    }
+}
+
+impl Symbols {
+    /// Loads the per-function breakdown stored alongside a result file.
+    pub fn load(result: &Path) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct BloatOutput {
+            file_size: u64,
+            text_section_size: u64,
+            functions: Vec<SymbolSize>,
+        }
+
+        let configuration = Configuration::for_result(result)?;
+        let output =
+            serde_json::from_slice::<BloatOutput>(&fs::read(Configuration::symbols_path(result))?)?;
+        Ok(Symbols {
+            configuration,
+            file_size: output.file_size,
+            text_section_size: output.text_section_size,
+            functions: output.functions,
+        })
+    }
+
+    /// Returns the `count` symbols whose size differ the most from `self` to
+    /// `other`. Symbols that show up more than once, as is common for
+    /// monomorphized functions once their hashes are stripped, are summed.
+    pub fn diff(&self, other: &Symbols, count: usize) -> Vec<SymbolDelta> {
+        let mut symbols = HashMap::<(Option<&str>, &str), (u64, u64)>::default();
+        for f in &self.functions {
+            symbols.entry((f.krate.as_deref(), &f.name)).or_default().0 += f.size;
+        }
+        for f in &other.functions {
+            symbols.entry((f.krate.as_deref(), &f.name)).or_default().1 += f.size;
+        }
+
+        let mut deltas = symbols
+            .into_iter()
+            .filter(|(_, (left, right))| left != right)
+            .map(|((krate, name), (left, right))| SymbolDelta {
+                krate: krate.map(str::to_owned),
+                name: name.to_owned(),
+                left,
+                right,
+            })
+            .collect::<Vec<_>>();
+        deltas.sort_by(|a, b| {
+            b.delta().abs().cmp(&a.delta().abs()).then_with(|| a.name.cmp(&b.name))
+        });
+        deltas.truncate(count);
+        deltas
+    }
+}
+
+fn with_extension(result: &Path, extension: &str) -> PathBuf {
+    let mut name = result.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(extension);
+    result.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Trimmed down `cargo bloat --crates --message-format=json` output.
+    const CRATES: &str = r#"{"file-size":3712904,"text-section-size":1804563,"crates":[{"name":"std","size":412305},{"name":"hyper","size":301224},{"name":"[Unknown]","size":1032}]}"#;
+
+    fn symbols(functions: &str) -> Symbols {
+        #[derive(Deserialize)]
+        struct BloatOutput {
+            functions: Vec<SymbolSize>,
+        }
+
+        Symbols {
+            configuration: configuration("hyper_full", Backend::Openssl),
+            file_size: 0,
+            text_section_size: 0,
+            functions: serde_json::from_str::<BloatOutput>(functions).unwrap().functions,
+        }
+    }
+
+    /// Directory removed once the test is done with it.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("bench-report-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn configuration(project: &str, backend: Backend) -> Configuration {
+        Configuration {
+            project: project.to_owned(),
+            backend,
+            profile: Profile { opt_level: OptLevel::Z, lto: Lto::Fat, codegen_units: 1 },
+        }
+    }
+
+    #[test]
+    fn file_name_round_trip() {
+        let openssl = configuration("hyper_full", Backend::Openssl);
+        assert_eq!(openssl.file_name(), "hyper_full_opt_z_lto_fat_cg_1");
+        assert_eq!(Configuration::from_file_name(&openssl.file_name()), Some(openssl));
+
+        let rust = configuration("hyper_full", Backend::Rust);
+        assert_eq!(rust.file_name(), "hyper_full_crypto_rust_opt_z_lto_fat_cg_1");
+        assert_eq!(Configuration::from_file_name(&rust.file_name()), Some(rust));
+
+        assert_eq!(Configuration::from_file_name("hyper_full_opt_4_lto_fat_cg_1"), None);
+        assert_eq!(Configuration::from_file_name("hyper_full"), None);
+    }
+
+    #[test]
+    fn load_without_sidecar() {
+        let dir = TempDir::new("without-sidecar");
+        let result = dir.0.join("actix_full_crypto_rust_opt_z_lto_fat_cg_1");
+        fs::write(&result, CRATES).unwrap();
+
+        let measurement = Measurement::load(&result).unwrap();
+        assert_eq!(measurement.configuration, configuration("actix_full", Backend::Rust));
+        assert_eq!(measurement.file_size, 3712904);
+        assert_eq!(measurement.text_section_size, 1804563);
+        assert_eq!(measurement.crate_size("hyper"), Some(301224));
+        assert_eq!(measurement.total(), 714561);
+    }
+
+    #[test]
+    fn load_with_sidecar() {
+        let dir = TempDir::new("with-sidecar");
+        let result = dir.0.join("renamed");
+        fs::write(&result, CRATES).unwrap();
+        assert!(matches!(Measurement::load(&result), Err(Error::UnknownConfiguration(_))));
+
+        let configuration = configuration("tide_surf", Backend::Rust);
+        configuration.write_sidecar(&result).unwrap();
+        assert_eq!(Measurement::load(&result).unwrap().configuration, configuration);
+    }
+
+    #[test]
+    fn load_dir_skips_sidecars_and_symbols() {
+        let dir = TempDir::new("load-dir");
+        let configuration = configuration("warp_surf", Backend::Openssl);
+        let result = dir.0.join(configuration.file_name());
+        fs::write(&result, CRATES).unwrap();
+        configuration.write_sidecar(&result).unwrap();
+        fs::write(Configuration::symbols_path(&result), r#"{"functions":[]}"#).unwrap();
+        fs::create_dir(dir.0.join("nested")).unwrap();
+
+        let measurements = Measurement::load_dir(&dir.0).unwrap();
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].configuration, configuration);
+    }
+
+    #[test]
+    fn diff() {
+        let left = symbols(
+            r#"{"functions":[
+                {"crate":"std","name":"std::rt::lang_start_internal","size":400},
+                {"crate":"hyper","name":"hyper::proto::h1::dispatch::poll_loop","size":300},
+                {"crate":"hyper","name":"hyper::proto::h1::dispatch::poll_loop","size":200},
+                {"crate":"bench_core","name":"bench_core::AppImpl::process","size":100},
+                {"crate":null,"name":"main","size":50}
+            ]}"#,
+        );
+        let right = symbols(
+            r#"{"functions":[
+                {"crate":"std","name":"std::rt::lang_start_internal","size":400},
+                {"crate":"hyper","name":"hyper::proto::h1::dispatch::poll_loop","size":450},
+                {"crate":"bench_core","name":"bench_core::AppImpl::process","size":40},
+                {"crate":"ring","name":"ring::digest::sha256","size":60}
+            ]}"#,
+        );
+
+        let delta = |krate: Option<&str>, name: &str, left, right| SymbolDelta {
+            krate: krate.map(str::to_owned),
+            name: name.to_owned(),
+            left,
+            right,
+        };
+        assert_eq!(
+            left.diff(&right, 10),
+            vec![
+                delta(Some("bench_core"), "bench_core::AppImpl::process", 100, 40),
+                delta(Some("ring"), "ring::digest::sha256", 0, 60),
+                delta(Some("hyper"), "hyper::proto::h1::dispatch::poll_loop", 500, 450),
+                delta(None, "main", 50, 0),
+            ]
+        );
+        assert_eq!(
+            left.diff(&right, 2),
+            vec![
+                delta(Some("bench_core"), "bench_core::AppImpl::process", 100, 40),
+                delta(Some("ring"), "ring::digest::sha256", 0, 60),
+            ]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright (C) 2020 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Description of an update flow, shared by `remote_mock`, which serves the
+//! responses, and [`run`](crate::run), which checks the `Info` reported after
+//! each of them.
+//!
+//! Scenarios are stored as JSON Lines, one step per line:
+//!
+//! ```json
+//! {"description": "Nothing to update yet", "response": {"status": 404}, "expect": {}}
+//! {"description": "Update applied", "response": {"signature": "valid"}, "expect": {"current_version": "0.0.2"}}
+//! ```
+//!
+//! A step without a `response` only fetches the local `Info`, while one with a
+//! `response` has the agent poll the remote once, on the fake clock, with the
+//! remote answering it with that response. Fields missing from `expect` take
+//! their default value, except `next_poll` and `validators`, which are only
+//! checked when given.
+//! A step with `"restart": true` has the app created anew before it, as when
+//! the device reboots. A step may also list `faults`, the ways the remote
+//! fails the agent's package requests before answering with the step's
+//! response, if any, all within the same poll. A step with
+//! `"block_downloads": true` has the agent fail to store payloads, which is
+//! then expected to fail its poll.
+
+use bench_core::Info;
+use serde::Deserialize;
+use std::{fmt, fs, io, path::Path};
+
+/// Scenario used when none is given to `remote_mock`.
+pub const DEFAULT_PATH: &str = "fixtures/scenarios/default.jsonl";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parsing { line: usize, error: serde_json::Error },
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Parsing { line, error } => write!(f, "invalid step at line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignatureKind {
+    /// Signed with the fixture key, so it matches the body.
+    Valid,
+    /// A well formed signature that doesn't match the body.
+    Invalid,
+    /// A header with characters outside of ASCII.
+    NonAscii,
+    /// A header that is not valid base64.
+    BadBase64,
+}
+
+/// How the remote fails a package request instead of answering it.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fault {
+    /// Keeps the connection open without ever answering, until the client
+    /// gives up.
+    Stall,
+    /// Closes the connection as soon as the request is received.
+    Reset,
+}
+
+/// What the remote does with a single package request.
+#[derive(Clone, Copy, Debug)]
+pub enum Answer<'a> {
+    Fault(Fault),
+    Response(&'a Response),
+}
+
+/// What the remote answers to a single package request.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Response {
+    #[serde(default = "Response::default_status")]
+    pub status: u16,
+    /// Signature sent in the `signature` header, omitted when unset.
+    pub signature: Option<SignatureKind>,
+    /// Body of the response, defaults to the default package.
+    pub body: Option<String>,
+    /// Sent in the `etag` header, with requests carrying the same one in
+    /// `if-none-match` answered with `304 Not Modified` instead.
+    pub etag: Option<String>,
+    /// Sent in the `last-modified` header, with requests carrying the same
+    /// date in `if-modified-since`, and no `if-none-match`, answered with
+    /// `304 Not Modified` instead.
+    pub last_modified: Option<String>,
+    /// How the payload requests that follow this response are answered.
+    #[serde(default)]
+    pub transfer: Transfer,
+    /// Report the agent has to send once it handled this response, checked by
+    /// `remote_mock`. Its `error` is only checked when given.
+    pub report: Option<bench_core::Report>,
+}
+
+/// Behaviour of the remote when serving payloads, which lets scenarios
+/// simulate flaky links.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct Transfer {
+    /// Closes the connection once this many bytes of the payload were sent.
+    pub drop_after: Option<u64>,
+    /// Stops sending once this many bytes of the payload were sent, keeping
+    /// the connection open until the client gives up.
+    pub stall_after: Option<u64>,
+    /// Whether `range` requests are honoured, instead of being answered with
+    /// the whole payload.
+    pub ranges: bool,
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Transfer { drop_after: None, stall_after: None, ranges: true }
+    }
+}
+
+impl Response {
+    fn default_status() -> u16 {
+        200
+    }
+
+    pub fn body(&self) -> Vec<u8> {
+        match &self.body {
+            Some(body) => body.as_bytes().to_vec(),
+            None => bench_core::Package::default().raw,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Step {
+    pub description: String,
+    /// Restarts the app before the step, with the state it saved.
+    #[serde(default)]
+    pub restart: bool,
+    /// Failed requests before the response, which the agent has to retry.
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+    /// Has a file in place of the download directory while the agent polls,
+    /// so payloads can't be stored and processing is expected to fail.
+    #[serde(default)]
+    pub block_downloads: bool,
+    pub response: Option<Response>,
+    pub expect: Info,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Scenario {
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let steps = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| {
+                serde_json::from_str(line).map_err(|error| Error::Parsing { line: n + 1, error })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Scenario { steps })
+    }
+
+    /// Answers to the package requests, in the order the remote has to give
+    /// them.
+    pub fn answers(&self) -> impl Iterator<Item = Answer<'_>> {
+        self.steps.iter().flat_map(|step| {
+            let faults = step.faults.iter().copied().map(Answer::Fault);
+            faults.chain(step.response.iter().map(Answer::Response))
+        })
+    }
+}
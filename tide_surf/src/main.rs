@@ -6,108 +6,221 @@ use derive_more::From;
 use futures_util::lock::Mutex;
 use http_client::h1::H1Client;
 use std::{
+    future::Future,
+    net::SocketAddr,
     ops::{Deref, DerefMut},
     sync::Arc,
+    time::Duration,
 };
 
-use bench::prelude::*;
-
-#[async_std::main]
-async fn main() {
-    let (url, _guards) = bench::start_remote_mock();
-    let local_client = LocalClient::new();
-    let remote_client = RemoteClient::new(&url);
-    let app = App::new(remote_client);
-    bench::run(local_client, app).await;
+fn main() {
+    let report = match bench::Setup::from_env() {
+        Ok(setup) => {
+            // The h1 client has no way to be given extra certificates, but the
+            // native-tls backend it uses loads the ones in `SSL_CERT_FILE` in
+            // place of the system's. It is set before the runtime starts any
+            // thread, as they could be reading the environment meanwhile
+            std::env::set_var("SSL_CERT_FILE", &setup.remote_ca.path);
+            async_std::task::block_on(bench::run::<LocalClient, App>(&setup))
+        }
+        Err(e) => bench::ScenarioReport::failed(e),
+    };
+    println!("{}", report.to_json());
+    std::process::exit(report.exit_code());
 }
 
 struct LocalClient {
     client: surf::Client<H1Client>,
+    url: String,
 }
 
 struct RemoteClient {
     client: surf::Client<H1Client>,
     remote: String,
+    /// Bounds the time until the response's head arrives, which is as close
+    /// to the time taken to connect as surf allows.
+    head_timeout: Duration,
+    timeout: Duration,
 }
 
 struct App {
-    info: Arc<Mutex<bench::Info>>,
+    info: Arc<Mutex<bench_core::Info>>,
     client: RemoteClient,
+    config: bench_core::Config,
 }
 
 #[derive(Debug, From)]
 enum Err {
     Http(tide::Error),
-    Parsing(bench::ParsingError),
     Io(std::io::Error),
+    Signature(bench_core::SignatureError),
+    Download(bench_core::DownloadError),
+    State(bench_core::StateError),
+    Report(bench_core::ReportError),
 }
 type Result<T> = std::result::Result<T, Err>;
 
 #[async_trait::async_trait(?Send)]
-impl bench::LocalClientImpl for LocalClient {
+impl bench_core::LocalClientImpl for LocalClient {
     type Err = Err;
 
-    fn new() -> Self {
-        LocalClient { client: surf::Client::new() }
+    fn new(server: SocketAddr) -> Self {
+        LocalClient { client: surf::Client::new(), url: format!("http://{}", server) }
     }
 
-    async fn fetch_info(&mut self) -> Result<bench::Info> {
-        Ok(self.client.get("http://127.0.0.1:8001").recv_json().await?)
+    async fn fetch_info(&mut self) -> Result<bench_core::Info> {
+        Ok(self.client.get(&self.url).recv_json().await?)
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::RemoteClientImpl for RemoteClient {
+impl bench_core::RemoteClientImpl for RemoteClient {
     type Err = Err;
 
-    fn new(remote: &str) -> Self {
-        RemoteClient { client: surf::Client::new(), remote: remote.to_owned() }
+    fn new(
+        remote: &str,
+        _ca: &bench_core::CaCertificate,
+        policy: &bench_core::RequestPolicy,
+    ) -> Result<Self> {
+        Ok(RemoteClient {
+            client: surf::Client::new(),
+            remote: remote.to_owned(),
+            head_timeout: policy.connect_timeout.min(policy.timeout),
+            timeout: policy.timeout,
+        })
+    }
+
+    fn is_transient(err: &Err) -> bool {
+        matches!(err, Err::Http(_) | Err::Io(_))
+    }
+
+    async fn fetch_package(
+        &mut self,
+        validators: &bench_core::Validators,
+    ) -> Result<bench_core::Fetched> {
+        use surf::http_types::StatusCode;
+
+        let mut request = self.client.get(&self.remote);
+        for (name, value) in validators.headers() {
+            request = request.header(name, value);
+        }
+        let head_timeout = self.head_timeout;
+        timeout(self.timeout, async move {
+            let mut response = timeout(head_timeout, async { Ok(request.await?) }).await?;
+
+            match response.status() {
+                StatusCode::Ok => {
+                    let signature = bench_core::Signature::from_header(
+                        response.header("signature").map(|value| value.as_str().as_bytes()),
+                    )?;
+                    let validators = bench_core::Validators::from_headers(
+                        response.header("etag").map(|value| value.as_str().as_bytes()),
+                        response.header("last-modified").map(|value| value.as_str().as_bytes()),
+                    );
+                    let body = response.body_bytes().await?;
+                    Ok(bench_core::Fetched::Package { body, signature, validators })
+                }
+                StatusCode::NotModified => Ok(bench_core::Fetched::NotModified),
+                _ => Ok(bench_core::Fetched::Nothing),
+            }
+        })
+        .await
     }
 
-    async fn fetch_package(&mut self) -> Result<Option<(bench::Package, bench::Signature)>> {
-        let mut response = self.client.get(&self.remote).await?;
+    async fn download_payload(&mut self, writer: &mut bench_core::PayloadWriter) -> Result<()> {
+        use futures_util::io::AsyncReadExt;
 
-        if let surf::http_types::StatusCode::Ok = response.status() {
-            let sign =
-                bench::Signature::from_base64_str(&response.header("signature").unwrap().as_str());
-            let pkg = bench::Package::parse(&response.body_bytes().await?)?;
-            return Ok(Some((pkg, sign)));
+        let url = writer.payload().url_from(&self.remote);
+        let mut request = self.client.get(&url);
+        if let Some(range) = writer.range() {
+            request = request.header("range", range);
         }
+        let head_timeout = self.head_timeout;
+        timeout(self.timeout, async move {
+            let mut response = timeout(head_timeout, async { Ok(request.await?) }).await?;
+
+            writer.begin(
+                response.status().into(),
+                response.header("content-range").map(|value| value.as_str().as_bytes()),
+            )?;
+            let mut buf = vec![0; 16 * 1024];
+            loop {
+                let n = response.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                writer.write(&buf[..n])?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn report(&mut self, report: &bench_core::Report) -> Result<()> {
+        let url = bench_core::Report::url_from(&self.remote);
+        let request = self.client.post(&url).body_json(report)?;
+        let response = timeout(self.head_timeout, async move { Ok(request.await?) }).await?;
+        Ok(bench_core::Report::accepted(response.status().into())?)
+    }
+}
 
-        Ok(None)
+/// Surf has no timeouts of its own, so requests are raced against timers, one
+/// for the whole exchange and a shorter one for the response's head, which
+/// bounds the time taken to connect.
+async fn timeout<T>(duration: Duration, request: impl Future<Output = Result<T>>) -> Result<T> {
+    use futures_util::future::{select, Either};
+
+    futures_util::pin_mut!(request);
+    match select(request, futures_timer::Delay::new(duration)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Err::Io(std::io::ErrorKind::TimedOut.into())),
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::AppImpl for App {
+impl bench_core::AppImpl for App {
     type Err = Err;
     type RemoteClient = RemoteClient;
 
-    fn new(client: RemoteClient) -> Self {
-        let info = Arc::default();
-        App { info, client }
+    fn new(client: RemoteClient, config: bench_core::Config) -> Result<Self> {
+        let info = Arc::new(Mutex::new(config.state.load()?));
+        Ok(App { info, client, config })
     }
 
-    fn serve(&mut self) -> Result<()> {
+    fn serve(&mut self, addr: SocketAddr, ready: bench_core::Ready) -> Result<()> {
         let state = self.info.clone();
         let mut app = tide::with_state(state);
-        app.at("/").get(|req: tide::Request<Arc<Mutex<bench::Info>>>| async move {
+        app.at("/").get(|req: tide::Request<Arc<Mutex<bench_core::Info>>>| async move {
             let state = &req.state().lock().await;
             let mut res = tide::Response::new(200);
             res.set_body(tide::Body::from_json(&state.deref())?);
             Ok(res)
         });
 
-        async_std::task::spawn(async { app.listen("127.0.0.1:8001").await });
+        // Tide doesn't report the address it binds to, so it is handed a
+        // listener bound here, which accepts connections from now on
+        let listener = std::net::TcpListener::bind(addr)?;
+        let bound = listener.local_addr()?;
+        async_std::task::spawn(async move {
+            if let Err(e) = app.listen(listener).await {
+                eprintln!("tide_surf: local server stopped: {}", e);
+            }
+        });
+        ready.notify(bound);
 
         Ok(())
     }
 
-    async fn map_info<F: FnOnce(&mut bench::Info)>(&mut self, f: F) -> Result<()> {
+    async fn map_info<F: FnOnce(&mut bench_core::Info)>(&mut self, f: F) -> Result<()> {
         Ok(f(self.info.lock().await.deref_mut()))
     }
 
     async fn client(&mut self) -> Result<&mut RemoteClient> {
         Ok(&mut self.client)
     }
+
+    fn config(&self) -> &bench_core::Config {
+        &self.config
+    }
 }
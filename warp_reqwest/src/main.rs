@@ -5,23 +5,24 @@
 use derive_more::From;
 use futures_util::lock::Mutex;
 use std::{
+    net::SocketAddr,
     ops::{Deref, DerefMut},
     sync::Arc,
 };
 
-use bench::prelude::*;
-
 #[tokio::main]
 async fn main() {
-    let (url, _guards) = bench::start_remote_mock();
-    let local_client = LocalClient::new();
-    let remote_client = RemoteClient::new(&url);
-    let app = App::new(remote_client);
-    bench::run(local_client, app).await;
+    let report = match bench::Setup::from_env() {
+        Ok(setup) => bench::run::<LocalClient, App>(&setup).await,
+        Err(e) => bench::ScenarioReport::failed(e),
+    };
+    println!("{}", report.to_json());
+    std::process::exit(report.exit_code());
 }
 
 struct LocalClient {
     client: reqwest::Client,
+    url: String,
 }
 
 struct RemoteClient {
@@ -30,65 +31,122 @@ struct RemoteClient {
 }
 
 struct App {
-    info: Arc<Mutex<bench::Info>>,
+    info: Arc<Mutex<bench_core::Info>>,
     client: RemoteClient,
+    config: bench_core::Config,
 }
 
 #[derive(Debug, From)]
 enum Err {
     Server(warp::Error),
     Client(reqwest::Error),
-    Parsing(bench::ParsingError),
+    Signature(bench_core::SignatureError),
+    Download(bench_core::DownloadError),
+    State(bench_core::StateError),
+    Report(bench_core::ReportError),
 }
 type Result<T> = std::result::Result<T, Err>;
 
 #[async_trait::async_trait(?Send)]
-impl bench::LocalClientImpl for LocalClient {
+impl bench_core::LocalClientImpl for LocalClient {
     type Err = Err;
 
-    fn new() -> Self {
-        LocalClient { client: reqwest::Client::new() }
+    fn new(server: SocketAddr) -> Self {
+        LocalClient { client: reqwest::Client::new(), url: format!("http://{}", server) }
     }
 
-    async fn fetch_info(&mut self) -> Result<bench::Info> {
-        Ok(self.client.get("http://localhost:8001").send().await?.json().await?)
+    async fn fetch_info(&mut self) -> Result<bench_core::Info> {
+        Ok(self.client.get(&self.url).send().await?.json().await?)
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::RemoteClientImpl for RemoteClient {
+impl bench_core::RemoteClientImpl for RemoteClient {
     type Err = Err;
 
-    fn new(remote: &str) -> Self {
-        RemoteClient { client: reqwest::Client::new(), remote: remote.to_owned() }
+    fn new(
+        remote: &str,
+        ca: &bench_core::CaCertificate,
+        policy: &bench_core::RequestPolicy,
+    ) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&ca.pem)?)
+            .connect_timeout(policy.connect_timeout)
+            .timeout(policy.timeout)
+            .build()?;
+        Ok(RemoteClient { client, remote: remote.to_owned() })
     }
 
-    async fn fetch_package(&mut self) -> Result<Option<(bench::Package, bench::Signature)>> {
-        let response = self.client.get(&self.remote).send().await?;
+    fn is_transient(err: &Err) -> bool {
+        // Statuses are handled by the agent, so any error from the client
+        // itself comes from the connection
+        matches!(err, Err::Client(_))
+    }
 
-        if let reqwest::StatusCode::OK = response.status() {
-            let sign = bench::Signature::from_base64_str(
-                &response.headers().get("Signature").unwrap().to_str().unwrap(),
-            );
-            let pkg = bench::Package::parse(&response.bytes().await?)?;
-            return Ok(Some((pkg, sign)));
+    async fn fetch_package(
+        &mut self,
+        validators: &bench_core::Validators,
+    ) -> Result<bench_core::Fetched> {
+        let mut request = self.client.get(&self.remote);
+        for (name, value) in validators.headers() {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let signature = bench_core::Signature::from_header(
+                    response.headers().get("Signature").map(|value| value.as_bytes()),
+                )?;
+                let validators = bench_core::Validators::from_headers(
+                    response.headers().get("etag").map(|value| value.as_bytes()),
+                    response.headers().get("last-modified").map(|value| value.as_bytes()),
+                );
+                let body = response.bytes().await?.to_vec();
+                Ok(bench_core::Fetched::Package { body, signature, validators })
+            }
+            reqwest::StatusCode::NOT_MODIFIED => Ok(bench_core::Fetched::NotModified),
+            _ => Ok(bench_core::Fetched::Nothing),
         }
+    }
 
-        Ok(None)
+    async fn download_payload(&mut self, writer: &mut bench_core::PayloadWriter) -> Result<()> {
+        let url = writer.payload().url_from(&self.remote);
+        let mut request = self.client.get(&url);
+        if let Some(range) = writer.range() {
+            request = request.header("range", range);
+        }
+        let mut response = request.send().await?;
+
+        writer.begin(
+            response.status().as_u16(),
+            response.headers().get("content-range").map(|value| value.as_bytes()),
+        )?;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write(&chunk)?;
+        }
+
+        Ok(())
+    }
+
+    async fn report(&mut self, report: &bench_core::Report) -> Result<()> {
+        let url = bench_core::Report::url_from(&self.remote);
+        let response = self.client.post(&url).json(report).send().await?;
+        Ok(bench_core::Report::accepted(response.status().as_u16())?)
     }
 }
 
 #[async_trait::async_trait(?Send)]
-impl bench::AppImpl for App {
+impl bench_core::AppImpl for App {
     type Err = Err;
     type RemoteClient = RemoteClient;
 
-    fn new(client: RemoteClient) -> Self {
-        let info = Arc::default();
-        App { info, client }
+    fn new(client: RemoteClient, config: bench_core::Config) -> Result<Self> {
+        let info = Arc::new(Mutex::new(config.state.load()?));
+        Ok(App { info, client, config })
     }
 
-    fn serve(&mut self) -> Result<()> {
+    fn serve(&mut self, addr: SocketAddr, ready: bench_core::Ready) -> Result<()> {
         use warp::{reject::Rejection, reply::Json, Filter};
 
         type Result = std::result::Result<Json, Rejection>;
@@ -102,16 +160,22 @@ impl bench::AppImpl for App {
             }
         });
 
-        tokio::spawn(warp::serve(route).run(([127, 0, 0, 1], 8001)));
+        let (bound, server) = warp::serve(route).try_bind_ephemeral(addr)?;
+        ready.notify(bound);
+        tokio::spawn(server);
 
         Ok(())
     }
 
-    async fn map_info<F: FnOnce(&mut bench::Info)>(&mut self, f: F) -> Result<()> {
+    async fn map_info<F: FnOnce(&mut bench_core::Info)>(&mut self, f: F) -> Result<()> {
         Ok(f(self.info.lock().await.deref_mut()))
     }
 
     async fn client(&mut self) -> Result<&mut RemoteClient> {
         Ok(&mut self.client)
     }
+
+    fn config(&self) -> &bench_core::Config {
+        &self.config
+    }
 }